@@ -0,0 +1,410 @@
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use eyre::{Result, eyre};
+use futures::future;
+use serde::Deserialize;
+use tokio::runtime::Handle;
+use tokio::sync::{OnceCell, Semaphore};
+
+use alloy::eips::{BlockId, BlockNumberOrTag};
+use alloy::primitives::{Address, B256, Bytes, U256};
+use alloy::providers::{Provider, RootProvider};
+use alloy::rpc::client::{BatchRequest, Waiter};
+use alloy::rpc::types::TransactionRequest;
+use alloy::transports::RpcError;
+use alloy::consensus::Header;
+use reth_ethereum::node::api::{NodePrimitives, NodeTypesWithDBAdapter};
+use reth_ethereum::node::EthereumNode;
+use reth_ethereum::provider::db::DatabaseEnv;
+use reth_ethereum::provider::providers::ProviderNodeTypes;
+
+use crate::error::PriceError;
+use crate::protocols::{call_output_word, StateRead, ViewCaller};
+use crate::reth_utils::{self, CallEvmConfig, LocalProviderFactory};
+
+
+pub const DEFAULT_RPC_BATCH_SIZE: usize = 100;
+pub const DEFAULT_RPC_MAX_CONCURRENCY: usize = 16;
+
+/// Value of one `StateRead`: `None` for an empty slot, `CallFailed` for a
+/// call that reverted or returned less than a word.
+pub type ReadValue = Result<Option<U256>, PriceError>;
+
+/// Source of historical headers and state the fetcher reads from.
+///
+/// Methods are blocking and meant to be called from rayon workers or
+/// `spawn_blocking` threads, never from a tokio worker.
+pub trait StateBackend: Send + Sync {
+
+    /// Most recent block the backend has state for.
+    fn latest_block(&self) -> Result<u64>;
+
+    /// `None` if the block's header is not available.
+    fn block_timestamp(&self, block_num: u64) -> Result<Option<u64>>;
+
+    /// Values of `reads` at the end of `block_num`. A failing call only fails
+    /// its own value, the `Err` is for the backend itself failing.
+    fn read_state(&self, block_num: u64, reads: &[StateRead]) -> Result<Vec<ReadValue>>;
+
+    /// `block_timestamp` of several blocks at once.
+    fn block_timestamps(&self, blocks: &[u64]) -> Result<Vec<Option<u64>>> {
+        blocks.iter().map(|&block_num| self.block_timestamp(block_num)).collect()
+    }
+
+    /// `read_state` of several `(block, reads)` requests at once.
+    fn read_states(&self, requests: &[(u64, Vec<StateRead>)]) -> Result<Vec<Vec<ReadValue>>> {
+        requests
+            .iter()
+            .map(|(block_num, reads)| self.read_state(*block_num, reads))
+            .collect()
+    }
+
+    /// Number of blocks worth handing to `block_timestamps`/`read_states`
+    /// together. Backends that pay per request batch across blocks; local
+    /// ones are better parallelized block by block.
+    fn blocks_per_chunk(&self) -> usize {
+        1
+    }
+
+    /// Whether `address` has code at the end of `block_num`.
+    fn has_code(&self, address: Address, block_num: u64) -> Result<bool>;
+
+    /// Blocks within `block_range` in which the slot was modified.
+    fn storage_change_blocks(
+        &self,
+        address: Address,
+        slot: B256,
+        block_range: Range<u64>,
+    ) -> Result<Vec<u64>>;
+
+    /// Caller for the view functions protocols and token metadata need,
+    /// executed against the latest state the backend has.
+    fn view_caller(&self) -> Result<Box<dyn ViewCaller>>;
+
+}
+
+/// Reads a local reth datadir of node type `N`, `EthereumNode` for reth and
+/// `OpNode` for op-reth.
+pub struct RethBackend<N: CallEvmConfig = EthereumNode> {
+    provider_factory: LocalProviderFactory<N>,
+    evm_config: N::EvmConfig,
+}
+
+impl<N> RethBackend<N>
+where
+    N: CallEvmConfig,
+    NodeTypesWithDBAdapter<N, Arc<DatabaseEnv>>: ProviderNodeTypes,
+{
+
+    pub fn new(db_path: &Path, chain_spec: Arc<N::ChainSpec>) -> Result<Self> {
+        let provider_factory = reth_utils::build_provider_factory::<N>(db_path, chain_spec.clone())?;
+        Ok(Self { provider_factory, evm_config: N::evm_config(chain_spec) })
+    }
+
+}
+
+impl<N> StateBackend for RethBackend<N>
+where
+    N: CallEvmConfig,
+    NodeTypesWithDBAdapter<N, Arc<DatabaseEnv>>: ProviderNodeTypes<Primitives: NodePrimitives<BlockHeader = Header>>,
+{
+
+    fn latest_block(&self) -> Result<u64> {
+        reth_utils::latest_block_number(&self.provider_factory)
+    }
+
+    fn block_timestamp(&self, block_num: u64) -> Result<Option<u64>> {
+        reth_utils::block_num_to_timestamp(&self.provider_factory, block_num)
+    }
+
+    fn has_code(&self, address: Address, block_num: u64) -> Result<bool> {
+        let hist_provider = self.provider_factory.history_by_block_number(block_num)?;
+        Ok(hist_provider.account_code(&address)?.is_some_and(|code| !code.is_empty()))
+    }
+
+    fn read_state(&self, block_num: u64, reads: &[StateRead]) -> Result<Vec<ReadValue>> {
+        let hist_provider = self.provider_factory.history_by_block_number(block_num)?;
+        reads
+            .iter()
+            .map(|read| match read {
+                StateRead::Storage { target, slot, .. } => Ok(Ok(hist_provider.storage(*target, *slot)?)),
+                StateRead::Call { target, input } => {
+                    let output = reth_utils::call_at_block(
+                        &self.provider_factory,
+                        &self.evm_config,
+                        block_num,
+                        *target,
+                        input.clone(),
+                    )?;
+                    Ok(call_value(*target, output))
+                }
+            })
+            .collect()
+    }
+
+    fn storage_change_blocks(
+        &self,
+        address: Address,
+        slot: B256,
+        block_range: Range<u64>,
+    ) -> Result<Vec<u64>> {
+        reth_utils::storage_change_blocks(&self.provider_factory, address, slot, block_range)
+    }
+
+    fn view_caller(&self) -> Result<Box<dyn ViewCaller>> {
+        Ok(Box::new(RethViewCaller {
+            block_num: reth_utils::latest_block_number(&self.provider_factory)?,
+            provider_factory: self.provider_factory.clone(),
+            evm_config: self.evm_config.clone(),
+        }))
+    }
+
+}
+
+/// Runs view calls in a local EVM over the reth state, without any network.
+struct RethViewCaller<N: CallEvmConfig> {
+    provider_factory: LocalProviderFactory<N>,
+    evm_config: N::EvmConfig,
+    block_num: u64,
+}
+
+#[async_trait::async_trait]
+impl<N> ViewCaller for RethViewCaller<N>
+where
+    N: CallEvmConfig,
+    NodeTypesWithDBAdapter<N, Arc<DatabaseEnv>>: ProviderNodeTypes<Primitives: NodePrimitives<BlockHeader = Header>>,
+{
+
+    async fn call(&self, to: Address, input: Bytes) -> Result<Bytes> {
+        reth_utils::call_at_block(&self.provider_factory, &self.evm_config, self.block_num, to, input)?
+            .map_err(|reason| eyre!("Call to {to} failed at block {}: {reason}", self.block_num))
+    }
+
+    async fn storage(&self, address: Address, slot: B256) -> Result<U256> {
+        let state = self.provider_factory.history_by_block_number(self.block_num)?;
+        Ok(state.storage(address, slot)?.unwrap_or_default())
+    }
+
+}
+
+/// Reads state from an archive node with `eth_getStorageAt`/`eth_getBlockByNumber`.
+///
+/// Header and state reads of consecutive blocks are sent together as
+/// JSON-RPC batches of at most `batch_size` calls, with at most
+/// `max_concurrency` requests in flight.
+pub struct RpcBackend {
+    provider: RootProvider,
+    runtime: Handle,
+    batch_size: usize,
+    request_permits: Semaphore,
+}
+
+impl RpcBackend {
+
+    /// Must be called from within a tokio runtime, which is later used to
+    /// drive the requests. Reads block on that runtime, so they must not be
+    /// made from its worker threads.
+    pub fn new(provider: RootProvider, batch_size: usize, max_concurrency: usize) -> Self {
+        Self {
+            provider,
+            runtime: Handle::current(),
+            batch_size: batch_size.max(1),
+            request_permits: Semaphore::new(max_concurrency.max(1)),
+        }
+    }
+
+    async fn fetch_block_timestamps(&self, blocks: &[u64]) -> Result<Vec<Option<u64>>> {
+        let futs = blocks.chunks(self.batch_size).map(|chunk| async move {
+            let _permit = self.request_permits.acquire().await?;
+            let mut batch = BatchRequest::new(self.provider.client());
+            let waiters = chunk
+                .iter()
+                .map(|&block_num| batch.add_call::<_, Option<BlockTimestamp>>(
+                    "eth_getBlockByNumber",
+                    &(BlockNumberOrTag::Number(block_num), false),
+                ))
+                .collect::<Result<Vec<_>, _>>()?;
+            batch.send().await?;
+            let mut timestamps = Vec::with_capacity(chunk.len());
+            for waiter in waiters {
+                timestamps.push(waiter.await?.map(|block| block.timestamp));
+            }
+            Ok::<_, eyre::Report>(timestamps)
+        });
+        Ok(future::try_join_all(futs).await?.into_iter().flatten().collect())
+    }
+
+    async fn fetch_has_code(&self, address: Address, block_num: u64) -> Result<bool> {
+        let _permit = self.request_permits.acquire().await?;
+        let code = self.provider.get_code_at(address).block_id(BlockId::number(block_num)).await?;
+        Ok(!code.is_empty())
+    }
+
+    async fn fetch_states(&self, requests: &[(u64, Vec<StateRead>)]) -> Result<Vec<Vec<ReadValue>>> {
+        let reads = requests
+            .iter()
+            .flat_map(|(block_num, reads)| reads.iter().map(|read| (*block_num, read)))
+            .collect::<Vec<_>>();
+        let futs = reads
+            .chunks(self.batch_size)
+            .map(|chunk| self.fetch_reads(chunk));
+        let mut values = future::try_join_all(futs).await?.into_iter().flatten();
+        Ok(requests
+            .iter()
+            .map(|(_, reads)| values.by_ref().take(reads.len()).collect())
+            .collect())
+    }
+
+    /// Sends `reads` as a single batch.
+    async fn fetch_reads(&self, reads: &[(u64, &StateRead)]) -> Result<Vec<ReadValue>> {
+        let _permit = self.request_permits.acquire().await?;
+        let mut batch = BatchRequest::new(self.provider.client());
+        let waiters = reads
+            .iter()
+            .map(|(block_num, read)| {
+                let block = BlockNumberOrTag::Number(*block_num);
+                match read {
+                    StateRead::Storage { target, slot, .. } => batch
+                        .add_call::<_, U256>("eth_getStorageAt", &(*target, U256::from_be_bytes(slot.0), block))
+                        .map(ReadWaiter::Storage),
+                    StateRead::Call { target, input } => {
+                        let tx = TransactionRequest::default().to(*target).input(input.clone().into());
+                        batch
+                            .add_call::<_, Bytes>("eth_call", &(tx, block))
+                            .map(|waiter| ReadWaiter::Call(*target, waiter))
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        batch.send().await?;
+        let mut values = Vec::with_capacity(reads.len());
+        for waiter in waiters {
+            let value = match waiter {
+                ReadWaiter::Storage(waiter) => {
+                    let value = waiter.await?;
+                    // nodes report empty slots as zero
+                    Ok((!value.is_zero()).then_some(value))
+                }
+                ReadWaiter::Call(target, waiter) => match waiter.await {
+                    Ok(output) => call_value(target, Ok(output)),
+                    // the node's error for this call alone, e.g. a revert
+                    Err(RpcError::ErrorResp(payload)) => call_value(target, Err(payload.message.to_string())),
+                    Err(error) => return Err(error.into()),
+                },
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+}
+
+/// The only field of an `eth_getBlockByNumber` response the fetcher needs.
+#[derive(Deserialize)]
+struct BlockTimestamp {
+    #[serde(with = "alloy::serde::quantity")]
+    timestamp: u64,
+}
+
+/// Pending response of one `StateRead` in a batch.
+enum ReadWaiter {
+    Storage(Waiter<U256>),
+    Call(Address, Waiter<Bytes>),
+}
+
+/// Value of a call to `target` from its output, or the reason it failed.
+fn call_value(target: Address, output: Result<Bytes, String>) -> ReadValue {
+    let output = output.map_err(|reason| PriceError::CallFailed { target, reason })?;
+    call_output_word(&output)
+        .map(Some)
+        .map_err(|error| PriceError::CallFailed { target, reason: error.to_string() })
+}
+
+impl StateBackend for RpcBackend {
+
+    fn latest_block(&self) -> Result<u64> {
+        self.runtime.block_on(async {
+            let _permit = self.request_permits.acquire().await?;
+            Ok(self.provider.get_block_number().await?)
+        })
+    }
+
+    fn block_timestamp(&self, block_num: u64) -> Result<Option<u64>> {
+        Ok(self.block_timestamps(&[block_num])?[0])
+    }
+
+    fn has_code(&self, address: Address, block_num: u64) -> Result<bool> {
+        self.runtime.block_on(self.fetch_has_code(address, block_num))
+    }
+
+    fn read_state(&self, block_num: u64, reads: &[StateRead]) -> Result<Vec<ReadValue>> {
+        let mut values = self.read_states(&[(block_num, reads.to_vec())])?;
+        Ok(values.remove(0))
+    }
+
+    fn block_timestamps(&self, blocks: &[u64]) -> Result<Vec<Option<u64>>> {
+        self.runtime.block_on(self.fetch_block_timestamps(blocks))
+    }
+
+    fn read_states(&self, requests: &[(u64, Vec<StateRead>)]) -> Result<Vec<Vec<ReadValue>>> {
+        self.runtime.block_on(self.fetch_states(requests))
+    }
+
+    fn blocks_per_chunk(&self) -> usize {
+        self.batch_size
+    }
+
+    fn storage_change_blocks(
+        &self,
+        _address: Address,
+        _slot: B256,
+        _block_range: Range<u64>,
+    ) -> Result<Vec<u64>> {
+        Err(eyre!("Storage change history is only available with the reth backend"))
+    }
+
+    fn view_caller(&self) -> Result<Box<dyn ViewCaller>> {
+        Ok(Box::new(RpcViewCaller {
+            provider: self.provider.clone(),
+            block: OnceCell::new(),
+        }))
+    }
+
+}
+
+/// Runs view calls on the node, pinned to the block that was latest at the
+/// first request so that calls and storage reads agree with each other.
+struct RpcViewCaller {
+    provider: RootProvider,
+    block: OnceCell<BlockId>,
+}
+
+impl RpcViewCaller {
+
+    async fn block(&self) -> Result<BlockId> {
+        self.block
+            .get_or_try_init(|| async {
+                Ok::<_, eyre::Report>(BlockId::number(self.provider.get_block_number().await?))
+            })
+            .await
+            .copied()
+    }
+
+}
+
+#[async_trait::async_trait]
+impl ViewCaller for RpcViewCaller {
+
+    async fn call(&self, to: Address, input: Bytes) -> Result<Bytes> {
+        let tx = TransactionRequest::default().to(to).input(input.into());
+        Ok(self.provider.call(tx).block(self.block().await?).await?)
+    }
+
+    async fn storage(&self, address: Address, slot: B256) -> Result<U256> {
+        let block = self.block().await?;
+        Ok(self.provider.get_storage_at(address, U256::from_be_bytes(slot.0)).block_id(block).await?)
+    }
+
+}
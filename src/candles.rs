@@ -0,0 +1,225 @@
+use alloy::primitives::U256;
+use serde::Serialize;
+
+use crate::PriceFetcherResult;
+
+
+/// OHLC bar of one source's prices over an interval of block timestamps.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Candle {
+    pub source: String,
+    /// Start of the interval in unix seconds, a multiple of its length.
+    pub bucket_start: u64,
+    /// Close of the previous interval if there is one, the first price otherwise.
+    #[serde(serialize_with = "serialize_u256_to_dec")]
+    pub open: U256,
+    #[serde(serialize_with = "serialize_u256_to_dec")]
+    pub high: U256,
+    #[serde(serialize_with = "serialize_u256_to_dec")]
+    pub low: U256,
+    #[serde(serialize_with = "serialize_u256_to_dec")]
+    pub close: U256,
+    /// `None` for intervals without samples, whose price is carried forward.
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+    pub sample_count: u64,
+}
+
+impl Candle {
+
+    fn carried(source: &str, bucket_start: u64, price: U256) -> Self {
+        Self {
+            source: source.to_string(),
+            bucket_start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            first_block: None,
+            last_block: None,
+            sample_count: 0,
+        }
+    }
+
+    fn add_sample(&mut self, block_num: u64, price: U256) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.first_block.get_or_insert(block_num);
+        self.last_block = Some(block_num);
+        self.sample_count += 1;
+    }
+
+}
+
+/// Groups the priced rows of each source into candles of `interval` seconds
+/// by block timestamp. Rows without a price are left out.
+///
+/// Rows are taken as the prices the source changed to, as with sampled or
+/// changed-only fetches: each price holds until the next row, so it opens the
+/// following candle and fills intervals without rows, up to the one
+/// containing `end_timestamp` (e.g. that of the last fetched block).
+pub fn aggregate_candles(prices: &[PriceFetcherResult], interval: u64, end_timestamp: Option<u64>) -> Vec<Candle> {
+    let mut rows = prices
+        .iter()
+        .filter_map(|row| Some((row, row.block_timestamp?, row.price?)))
+        .collect::<Vec<_>>();
+    rows.sort_by(|(a, ..), (b, ..)| a.source.cmp(&b.source).then(a.block_num.cmp(&b.block_num)));
+
+    let bucket_of = |timestamp: u64| timestamp / interval * interval;
+    let mut candles = Vec::<Candle>::new();
+    for (k, &(row, timestamp, price)) in rows.iter().enumerate() {
+        let bucket_start = bucket_of(timestamp);
+        match candles.last_mut() {
+            Some(candle) if candle.source == row.source && candle.bucket_start == bucket_start => {
+                candle.add_sample(row.block_num, price);
+            }
+            Some(candle) if candle.source == row.source => {
+                let close = candle.close;
+                fill_candles(&mut candles, &row.source, bucket_start, interval, close);
+                let mut candle = Candle::carried(&row.source, bucket_start, close);
+                candle.add_sample(row.block_num, price);
+                candles.push(candle);
+            }
+            _ => {
+                let mut candle = Candle::carried(&row.source, bucket_start, price);
+                candle.add_sample(row.block_num, price);
+                candles.push(candle);
+            }
+        }
+        let source_ends = rows.get(k + 1).is_none_or(|(next, ..)| next.source != row.source);
+        if let (true, Some(end_timestamp)) = (source_ends, end_timestamp) {
+            let close = candles.last().expect("candle pushed above").close;
+            fill_candles(&mut candles, &row.source, bucket_of(end_timestamp) + interval, interval, close);
+        }
+    }
+    candles
+}
+
+/// Pushes candles at `price` for the intervals after the last candle and
+/// before `until`.
+fn fill_candles(candles: &mut Vec<Candle>, source: &str, until: u64, interval: u64, price: U256) {
+    let mut bucket_start = candles.last().expect("filling after a candle").bucket_start + interval;
+    while bucket_start < until {
+        candles.push(Candle::carried(source, bucket_start, price));
+        bucket_start += interval;
+    }
+}
+
+fn serialize_u256_to_dec<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, U256};
+    use crate::{PriceError, PriceFetcherResult, PriceStatus};
+    use super::{Candle, aggregate_candles};
+
+    fn row(source: &str, block_num: u64, block_timestamp: u64, price: Option<u64>) -> PriceFetcherResult {
+        PriceFetcherResult {
+            block_num,
+            block_timestamp: Some(block_timestamp),
+            source: source.to_string(),
+            protocol: "univ3".to_string(),
+            pool_address: Address::ZERO,
+            price: price.map(U256::from),
+            quote_token: Address::ZERO,
+            base_token: Address::ZERO,
+            status: if price.is_some() { PriceStatus::Ok } else { PriceStatus::ZeroLiquidity },
+            error: price.is_none().then_some(PriceError::ZeroLiquidity),
+        }
+    }
+
+    fn candle(source: &str, bucket_start: u64, ohlc: [u64; 4], blocks: Option<(u64, u64)>, sample_count: u64) -> Candle {
+        let [open, high, low, close] = ohlc.map(U256::from);
+        Candle {
+            source: source.to_string(),
+            bucket_start,
+            open,
+            high,
+            low,
+            close,
+            first_block: blocks.map(|(first, _)| first),
+            last_block: blocks.map(|(_, last)| last),
+            sample_count,
+        }
+    }
+
+    #[test]
+    fn buckets_start_at_multiples_of_the_interval() {
+        let prices = [
+            row("a", 1, 60, Some(10)),
+            row("a", 2, 119, Some(12)),
+            // the boundary itself starts the next interval
+            row("a", 3, 120, Some(8)),
+            row("a", 4, 179, Some(9)),
+        ];
+        assert_eq!(aggregate_candles(&prices, 60, None), [
+            candle("a", 60, [10, 12, 10, 12], Some((1, 2)), 2),
+            candle("a", 120, [12, 12, 8, 9], Some((3, 4)), 2),
+        ]);
+    }
+
+    #[test]
+    fn sources_are_aggregated_separately() {
+        let prices = [
+            row("b", 1, 0, Some(100)),
+            row("a", 1, 0, Some(1)),
+            row("b", 2, 60, Some(90)),
+            row("a", 2, 60, Some(2)),
+        ];
+        assert_eq!(aggregate_candles(&prices, 60, None), [
+            candle("a", 0, [1, 1, 1, 1], Some((1, 1)), 1),
+            candle("a", 60, [1, 2, 1, 2], Some((2, 2)), 1),
+            candle("b", 0, [100, 100, 100, 100], Some((1, 1)), 1),
+            candle("b", 60, [100, 100, 90, 90], Some((2, 2)), 1),
+        ]);
+    }
+
+    #[test]
+    fn rows_without_price_are_left_out() {
+        let prices = [
+            row("a", 1, 0, Some(5)),
+            row("a", 2, 10, None),
+            row("a", 3, 20, Some(7)),
+            row("b", 1, 0, None),
+        ];
+        assert_eq!(aggregate_candles(&prices, 60, None), [
+            candle("a", 0, [5, 7, 5, 7], Some((1, 3)), 2),
+        ]);
+    }
+
+    #[test]
+    fn out_of_order_rows_are_sorted_by_block() {
+        let prices = [
+            row("a", 3, 30, Some(3)),
+            row("a", 1, 10, Some(1)),
+            row("a", 2, 20, Some(2)),
+        ];
+        assert_eq!(aggregate_candles(&prices, 60, None), [
+            candle("a", 0, [1, 3, 1, 3], Some((1, 3)), 3),
+        ]);
+    }
+
+    /// Changed-only and sampled rows leave intervals without samples, in which
+    /// the last price still holds.
+    #[test]
+    fn prices_are_carried_across_empty_intervals() {
+        let prices = [
+            row("a", 1, 0, Some(10)),
+            row("a", 50, 200, Some(20)),
+        ];
+        assert_eq!(aggregate_candles(&prices, 60, Some(350)), [
+            candle("a", 0, [10, 10, 10, 10], Some((1, 1)), 1),
+            candle("a", 60, [10, 10, 10, 10], None, 0),
+            candle("a", 120, [10, 10, 10, 10], None, 0),
+            candle("a", 180, [10, 20, 10, 20], Some((50, 50)), 1),
+            candle("a", 240, [20, 20, 20, 20], None, 0),
+            candle("a", 300, [20, 20, 20, 20], None, 0),
+        ]);
+    }
+}
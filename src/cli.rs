@@ -1,13 +1,15 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::ops::Range;
 
 
+/// `start..end` range of blocks, or `start..` for one up to the latest block.
 #[derive(Debug, Clone)]
 pub struct BlockRange {
     start: u64,
-    end: u64,
+    end: Option<u64>,
 }
 
 impl FromStr for BlockRange {
@@ -16,17 +18,20 @@ impl FromStr for BlockRange {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let parts: Vec<&str> = s.split("..").collect();
         if parts.len() != 2 {
-            return Err("Block range must be in format 'start..end'".to_string());
+            return Err("Block range must be in format 'start..end' or 'start..'".to_string());
         }
 
         let start = parts[0]
             .parse::<u64>()
             .map_err(|_| "Failed to parse start block as u64".to_string())?;
-        let end = parts[1]
-            .parse::<u64>()
-            .map_err(|_| "Failed to parse end block as u64".to_string())?;
-
-        if start >= end {
+        let end = match parts[1] {
+            "" => None,
+            end => Some(end
+                .parse::<u64>()
+                .map_err(|_| "Failed to parse end block as u64".to_string())?),
+        };
+
+        if end.is_some_and(|end| start >= end) {
             return Err("Start block must be less than end block".to_string());
         }
 
@@ -34,12 +39,90 @@ impl FromStr for BlockRange {
     }
 }
 
-impl From<BlockRange> for Range<u64> {
-    fn from(range: BlockRange) -> Self {
+/// Half-open range of UTC times. Each end is an RFC 3339 timestamp or a
+/// `YYYY-MM-DD` date, which stands for its midnight.
+#[derive(Debug, Clone)]
+pub struct TimeRange {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| "Time range must be in format 'start..end'".to_string())?;
+
+        let start = parse_time(start)?;
+        let end = parse_time(end)?;
+
+        if start >= end {
+            return Err("Start time must be before end time".to_string());
+        }
+
+        Ok(TimeRange { start, end })
+    }
+}
+
+impl From<TimeRange> for Range<DateTime<Utc>> {
+    fn from(range: TimeRange) -> Self {
         range.start..range.end
     }
 }
 
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.to_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        .map_err(|_| format!("Failed to parse {s:?} as an RFC 3339 time or a YYYY-MM-DD date"))
+}
+
+/// Parses a positive duration such as `30s`, `15m`, `1h`, `7d` or `2w`.
+pub fn parse_duration(s: &str) -> Result<TimeDelta, String> {
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(unit_start);
+    let amount = amount
+        .parse::<i64>()
+        .map_err(|_| format!("Failed to parse the amount of duration {s:?}"))?;
+
+    let duration = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("Duration {s:?} must end in one of the units s, m, h, d or w")),
+    }
+    .ok_or_else(|| format!("Duration {s:?} is too long"))?;
+
+    if duration.is_zero() {
+        return Err("Duration must be positive".to_string());
+    }
+    Ok(duration)
+}
+
+/// Blocks to fetch, either by number or by the times of their headers.
+pub enum FetchRange {
+    /// Up to the latest block if `end` is `None`.
+    Blocks { start: u64, end: Option<u64> },
+    Times(Range<DateTime<Utc>>),
+}
+
+/// Blocks of the range prices are fetched at.
+#[derive(Debug, Clone, Copy)]
+pub enum Sampling {
+    EveryBlock,
+    /// A source's first block and the blocks its storage changed in.
+    Changed,
+    EveryNBlocks(u64),
+    /// Last block at or before each multiple of the interval since the unix epoch.
+    Interval(TimeDelta),
+}
+
 #[derive(Parser)]
 pub struct Cli {
     #[command(subcommand)]
@@ -52,12 +135,23 @@ pub enum Commands {
 }
 
 #[derive(Args)]
+#[command(group(ArgGroup::new("range").args(["block_range", "time_range", "last"])))]
 pub struct FetchPricesArgs {
     #[arg(long, default_value = "1")]
     pub chain_id: u64,
 
+    /// Blocks `start..end` or `start..`, defaulting to the chain's
+    /// `default_start_block..default_end_block`.
     #[arg(long, value_parser = BlockRange::from_str)]
-    pub block_range: BlockRange,
+    pub block_range: Option<BlockRange>,
+
+    /// Blocks with timestamps in `start..end`, e.g. `2024-01-01T00:00:00Z..2024-02-01`.
+    #[arg(long, value_parser = TimeRange::from_str)]
+    pub time_range: Option<TimeRange>,
+
+    /// Blocks of the last duration up to now, e.g. `7d`.
+    #[arg(long, value_parser = parse_duration)]
+    pub last: Option<TimeDelta>,
 
     #[arg(long)]
     pub config_file_path: Option<PathBuf>,
@@ -67,6 +161,58 @@ pub struct FetchPricesArgs {
 
     #[arg(long)]
     pub label: Option<String>,
+
+    /// Only emit a source's price at blocks where its storage changed.
+    #[arg(long)]
+    pub changed_only: bool,
+
+    /// Only fetch every `N`th block of the range, starting with its first.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["changed_only", "interval"])]
+    pub every_n_blocks: Option<u64>,
+
+    /// Only fetch the last block at or before each interval boundary, e.g. `1h`
+    /// for hourly prices. Boundaries are multiples of the interval since the unix epoch.
+    #[arg(long, value_parser = parse_duration, conflicts_with = "changed_only")]
+    pub interval: Option<TimeDelta>,
+
+    /// Also write OHLC candles of this length, e.g. `1h`, to `candles.parquet`.
+    /// Intervals without rows carry the previous close forward.
+    #[arg(long, value_parser = parse_duration)]
+    pub candle_interval: Option<TimeDelta>,
+
+    /// Emit rows that can't be priced with a null price and their status
+    /// instead of aborting.
+    #[arg(long)]
+    pub tolerant: bool,
+}
+
+impl FetchPricesArgs {
+
+    /// `None` if no range was given.
+    pub fn fetch_range(&self) -> Option<FetchRange> {
+        match (&self.block_range, &self.time_range, self.last) {
+            (Some(block_range), _, _) => Some(FetchRange::Blocks {
+                start: block_range.start,
+                end: block_range.end,
+            }),
+            (_, Some(time_range), _) => Some(FetchRange::Times(time_range.clone().into())),
+            (_, _, Some(last)) => {
+                let now = Utc::now();
+                Some(FetchRange::Times(now - last..now))
+            }
+            (None, None, None) => None,
+        }
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        match (self.changed_only, self.every_n_blocks, self.interval) {
+            (true, _, _) => Sampling::Changed,
+            (_, Some(n), _) => Sampling::EveryNBlocks(n),
+            (_, _, Some(interval)) => Sampling::Interval(interval),
+            (false, None, None) => Sampling::EveryBlock,
+        }
+    }
+
 }
 
 pub fn parse_cli_args() -> Commands {
@@ -3,7 +3,11 @@ use serde::Deserialize;
 use eyre::Result;
 use url::Url;
 use alloy::primitives::{U256, uint};
-use crate::protocols::{UniV2, UniV3, BoxedProtocol};
+use crate::backend::{DEFAULT_RPC_BATCH_SIZE, DEFAULT_RPC_MAX_CONCURRENCY};
+use crate::protocols::{
+    Algebra, BalancerV2, CbEth, Chainlink, Curve, Erc4626, REth, Solidly, UniV2, UniV3, UniV4, WstEth,
+    BoxedProtocol,
+};
 
 
 #[derive(Deserialize, Debug, Clone)]
@@ -12,6 +16,16 @@ use crate::protocols::{UniV2, UniV3, BoxedProtocol};
 pub enum ProtocolType {
     UniV3(UniV3),
     UniV2(UniV2),
+    UniV4(UniV4),
+    Curve(Curve),
+    BalancerV2(BalancerV2),
+    Chainlink(Chainlink),
+    Solidly(Solidly),
+    Algebra(Algebra),
+    Erc4626(Erc4626),
+    WstEth(WstEth),
+    REth(REth),
+    CbEth(CbEth),
 }
 
 impl ProtocolType {
@@ -20,6 +34,34 @@ impl ProtocolType {
         match self {
             Self::UniV3(protocol) => Box::new(protocol),
             Self::UniV2(protocol) => Box::new(protocol),
+            Self::UniV4(protocol) => Box::new(protocol),
+            Self::Curve(protocol) => Box::new(protocol),
+            Self::BalancerV2(protocol) => Box::new(protocol),
+            Self::Chainlink(protocol) => Box::new(protocol),
+            Self::Solidly(protocol) => Box::new(protocol),
+            Self::Algebra(protocol) => Box::new(protocol),
+            Self::Erc4626(protocol) => Box::new(protocol),
+            Self::WstEth(protocol) => Box::new(protocol),
+            Self::REth(protocol) => Box::new(protocol),
+            Self::CbEth(protocol) => Box::new(protocol),
+        }
+    }
+
+    /// The `type` tag the protocol is configured with.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::UniV3(_) => "univ3",
+            Self::UniV2(_) => "univ2",
+            Self::UniV4(_) => "univ4",
+            Self::Curve(_) => "curve",
+            Self::BalancerV2(_) => "balancerv2",
+            Self::Chainlink(_) => "chainlink",
+            Self::Solidly(_) => "solidly",
+            Self::Algebra(_) => "algebra",
+            Self::Erc4626(_) => "erc4626",
+            Self::WstEth(_) => "wsteth",
+            Self::REth(_) => "reth",
+            Self::CbEth(_) => "cbeth",
         }
     }
 
@@ -31,21 +73,72 @@ pub struct Config {
     pub precision: u8,
 }
 
+/// Where historical headers and storage are read from.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendType {
+    /// Local reth database at `reth_db_path`.
+    #[default]
+    Reth,
+    /// Archive node at `rpc_url`.
+    Rpc,
+}
+
+/// Client that wrote the reth datadir, which fixes its table and header types.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeType {
+    /// reth, for Ethereum mainnet and testnets.
+    #[default]
+    Ethereum,
+    /// op-reth, for OP-stack chains such as Optimism and Base.
+    Optimism,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ChainConfig {
     pub chain_id: u64,
-    pub rpc_url: Url,
-    pub reth_db_path: PathBuf,
+    pub rpc_url: Option<Url>,
+    #[serde(default)]
+    pub backend: BackendType,
+    pub reth_db_path: Option<PathBuf>,
+    #[serde(default)]
+    pub node_type: NodeType,
+    /// Genesis JSON for chains without a built-in chainspec (mainnet, sepolia,
+    /// holesky, and optimism, base and their sepolias for op-reth).
+    pub genesis_path: Option<PathBuf>,
+    #[serde(default = "default_rpc_batch_size")]
+    pub rpc_batch_size: usize,
+    #[serde(default = "default_rpc_max_concurrency")]
+    pub rpc_max_concurrency: usize,
     pub default_start_block: u64,
     pub default_end_block: u64,
     pub price_sources: Vec<PriceSource>,
 }
 
+fn default_rpc_batch_size() -> usize {
+    DEFAULT_RPC_BATCH_SIZE
+}
+
+fn default_rpc_max_concurrency() -> usize {
+    DEFAULT_RPC_MAX_CONCURRENCY
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PriceSource {
     pub name: String,
     pub inverse_it: bool,
     pub protocol: ProtocolType,
+    /// First block to fetch the source at, intersected with the requested range.
+    pub start_block: Option<u64>,
+    /// Block to stop fetching the source before (exclusive).
+    pub end_block: Option<u64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
 }
 
 impl Config {
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use alloy::primitives::{Address, B256};
+use serde::{Deserialize, Serialize};
+
+
+/// Outcome of pricing a source at a block, written as the `status` column.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceStatus {
+    Ok,
+    ZeroLiquidity,
+    Overflow,
+    EmptySlot,
+    HeaderMissing,
+    NotDeployed,
+    InvalidState,
+    CallFailed,
+}
+
+/// Why a source could not be priced at a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PriceError {
+    /// A reserve, balance or supply the price divides by is zero.
+    ZeroLiquidity,
+    /// The price or one of its intermediates does not fit its integer type.
+    Overflow,
+    /// A required storage slot is empty at the block.
+    EmptySlot { target: Address, slot: B256 },
+    /// The block's header is not available.
+    HeaderMissing,
+    /// A contract the source reads has no code yet at the block.
+    NotDeployed { target: Address },
+    /// State that decodes but can't be priced, e.g. a negative oracle answer.
+    InvalidState(String),
+    /// A call the source is priced from reverted or returned less than a word.
+    CallFailed { target: Address, reason: String },
+}
+
+impl PriceError {
+
+    pub fn status(&self) -> PriceStatus {
+        match self {
+            Self::ZeroLiquidity => PriceStatus::ZeroLiquidity,
+            Self::Overflow => PriceStatus::Overflow,
+            Self::EmptySlot { .. } => PriceStatus::EmptySlot,
+            Self::HeaderMissing => PriceStatus::HeaderMissing,
+            Self::NotDeployed { .. } => PriceStatus::NotDeployed,
+            Self::InvalidState(_) => PriceStatus::InvalidState,
+            Self::CallFailed { .. } => PriceStatus::CallFailed,
+        }
+    }
+
+}
+
+impl fmt::Display for PriceError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroLiquidity => write!(f, "zero liquidity"),
+            Self::Overflow => write!(f, "arithmetic overflow"),
+            Self::EmptySlot { target, slot } => write!(f, "storage slot {slot} of {target} empty"),
+            Self::HeaderMissing => write!(f, "header missing"),
+            Self::NotDeployed { target } => write!(f, "{target} not deployed yet"),
+            Self::InvalidState(reason) => write!(f, "invalid state: {reason}"),
+            Self::CallFailed { target, reason } => write!(f, "call to {target} failed: {reason}"),
+        }
+    }
+
+}
+
+impl std::error::Error for PriceError {}
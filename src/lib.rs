@@ -1,17 +1,37 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+
+mod backend;
 mod price_fetcher;
 mod protocols;
 mod config;
+mod error;
 mod reth_utils;
+mod candles;
 pub mod writer;
 
-pub use price_fetcher::{PriceFetcherBuilder, PriceFetcher, PriceFetcherResult};
-pub use config::{Config, ChainConfig};
+pub use price_fetcher::{PriceFetcherBuilder, PriceFetcher, PriceFetcherResult, SourceRange, SourceDescriptor, TokenDescriptor};
+pub use error::{PriceError, PriceStatus};
+pub use config::{Config, ChainConfig, BackendType, NodeType};
+pub use candles::{Candle, aggregate_candles};
 
 #[derive(serde::Serialize, Debug)]
 pub struct PricesMetadata {
     pub chain_id: u64, 
     pub start_block: u64,
     pub end_block: u64,
-    pub sources: Vec<String>,
+    /// Requested times the block range was resolved from, if given as times.
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    /// Sampling of the block range, if not every block was fetched.
+    pub every_n_blocks: Option<u64>,
+    pub interval_secs: Option<u64>,
+    /// Length of the candles in `candles.parquet`, if requested.
+    pub candle_interval_secs: Option<u64>,
+    pub sources: Vec<SourceDescriptor>,
+    /// Range each source was fetched over, clipped to its deployment block.
+    pub source_ranges: Vec<SourceRange>,
     pub precision: u8,
+    /// Number of rows per source and failure status, for rows that could not be priced.
+    pub error_counts: BTreeMap<String, BTreeMap<PriceStatus, u64>>,
 }
\ No newline at end of file
@@ -2,15 +2,24 @@ mod cli;
 
 use std::path::{PathBuf, Path};
 use std::ops::Range;
-use eyre::Result;
+use std::collections::BTreeMap;
+use eyre::{Result, eyre};
+use chrono::{DateTime, Utc};
 use uuid_b64::UuidB64;
 use pool_price_fetcher::{
+    PriceFetcher,
     PriceFetcherBuilder,
     PriceFetcherResult,
+    PricesMetadata,
+    Candle,
+    PriceStatus,
+    SourceRange,
+    SourceDescriptor,
     ChainConfig,
     Config,
     self,
 };
+use cli::{FetchRange, Sampling};
 
 
 #[tokio::main]
@@ -26,6 +35,8 @@ const DEFAULT_CONFIG_PATH: &str = "./config.toml";
 const DEFAULT_DATA_DIR: &str = "./.data";
 
 async fn handle_fetch_prices_command(cli_args: cli::FetchPricesArgs) -> Result<()> {
+    let fetch_range = cli_args.fetch_range();
+    let sampling = cli_args.sampling();
     let config_path = cli_args.config_file_path
         .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
     let config = Config::try_from_file(&config_path)?;
@@ -36,6 +47,10 @@ async fn handle_fetch_prices_command(cli_args: cli::FetchPricesArgs) -> Result<(
             chain_config.chain_id == cli_args.chain_id
         })
         .ok_or_else(|| eyre::eyre!("Chain with ID {} not found in config", cli_args.chain_id))?;
+    let fetch_range = fetch_range.unwrap_or(FetchRange::Blocks {
+        start: chain_config.default_start_block,
+        end: Some(chain_config.default_end_block),
+    });
 
     let write_dir = cli_args.write_dir
         .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR));
@@ -45,9 +60,11 @@ async fn handle_fetch_prices_command(cli_args: cli::FetchPricesArgs) -> Result<(
     fetch_and_write_prices(
         chain_config,
         precision,
-        cli_args.block_range.into(),
-        &write_dir,
-        &label,
+        fetch_range,
+        sampling,
+        cli_args.tolerant,
+        cli_args.candle_interval.map(|interval| interval.num_seconds() as u64),
+        &write_dir.join(label),
     ).await?;
     
     Ok(())
@@ -56,40 +73,155 @@ async fn handle_fetch_prices_command(cli_args: cli::FetchPricesArgs) -> Result<(
 async fn fetch_and_write_prices(
     chain_config: ChainConfig,
     precision: u8,
-    block_range: Range<u64>,
+    fetch_range: FetchRange,
+    sampling: Sampling,
+    tolerant: bool,
+    candle_interval: Option<u64>,
     write_dir: &PathBuf,
-    label: &str,
 ) -> Result<()> {
     let chain_id = chain_config.chain_id;
-    let sources = chain_config.price_sources.iter()
-        .map(|source| source.protocol.clone().into_boxed().name())
-        .collect::<Vec<_>>();
-    let prices = fetch_prices_for_chain(
+    let time_range = match &fetch_range {
+        FetchRange::Times(time_range) => Some(time_range.clone()),
+        FetchRange::Blocks { .. } => None,
+    };
+    let fetched = fetch_prices_for_chain(
         chain_config,
         precision,
-        block_range.clone()
+        fetch_range,
+        sampling,
+        tolerant,
     ).await?;
+    let error_counts = count_errors(&fetched.prices);
+    let candles = candle_interval
+        .map(|interval| pool_price_fetcher::aggregate_candles(&fetched.prices, interval, fetched.end_timestamp));
 
-    let write_dir = write_dir.join(label);
-    create_dir(&write_dir)?;
-    write_prices(prices, &write_dir)?;
-    write_metadata(chain_id, sources, precision, block_range, &write_dir)?;
+    create_dir(write_dir)?;
+    write_prices(fetched.prices, write_dir)?;
+    if let Some(candles) = candles {
+        write_candles(candles, write_dir)?;
+    }
+    let metadata = PricesMetadata {
+        chain_id,
+        start_block: fetched.block_range.start,
+        end_block: fetched.block_range.end,
+        start_time: time_range.as_ref().map(|range| range.start),
+        end_time: time_range.map(|range| range.end),
+        every_n_blocks: match sampling {
+            Sampling::EveryNBlocks(n) => Some(n),
+            _ => None,
+        },
+        interval_secs: match sampling {
+            Sampling::Interval(interval) => Some(interval.num_seconds() as u64),
+            _ => None,
+        },
+        candle_interval_secs: candle_interval,
+        precision,
+        sources: fetched.sources,
+        source_ranges: fetched.source_ranges,
+        error_counts,
+    };
+    write_metadata(metadata, write_dir)?;
     Ok(())
 }
 
+struct FetchedPrices {
+    prices: Vec<PriceFetcherResult>,
+    sources: Vec<SourceDescriptor>,
+    source_ranges: Vec<SourceRange>,
+    /// Blocks fetched, after resolving a time range.
+    block_range: Range<u64>,
+    /// Timestamp of the last block fetched, up to which candles carry prices.
+    end_timestamp: Option<u64>,
+}
+
 async fn fetch_prices_for_chain(
     chain_config: ChainConfig,
     precision: u8,
-    block_range: Range<u64>,
-) -> Result<Vec<PriceFetcherResult>> {
-    let price_fetcher = PriceFetcherBuilder::default()
+    fetch_range: FetchRange,
+    sampling: Sampling,
+    tolerant: bool,
+) -> Result<FetchedPrices> {
+    let mut builder = PriceFetcherBuilder::default()
         .precision(precision)
-        .reth_db_path(&chain_config.reth_db_path)
-        .rpc_url(chain_config.rpc_url)
-        .price_sources(chain_config.price_sources)
-        .build()
-        .await?;
-    price_fetcher.fetch_prices(block_range)
+        .chain_id(chain_config.chain_id)
+        .backend(chain_config.backend)
+        .node_type(chain_config.node_type)
+        .rpc_batch_size(chain_config.rpc_batch_size)
+        .rpc_max_concurrency(chain_config.rpc_max_concurrency)
+        .record_price_errors(tolerant)
+        .price_sources(chain_config.price_sources);
+    if let Some(reth_db_path) = &chain_config.reth_db_path {
+        builder = builder.reth_db_path(reth_db_path);
+    }
+    if let Some(genesis_path) = &chain_config.genesis_path {
+        builder = builder.genesis_path(genesis_path);
+    }
+    if let Some(rpc_url) = chain_config.rpc_url {
+        builder = builder.rpc_url(rpc_url);
+    }
+    let price_fetcher = builder.build().await?;
+    tokio::task::spawn_blocking(move || fetch_prices_blocking(&price_fetcher, fetch_range, sampling)).await?
+}
+
+/// Fetches with the blocking `PriceFetcher` methods, off the runtime's workers.
+fn fetch_prices_blocking(
+    price_fetcher: &PriceFetcher,
+    fetch_range: FetchRange,
+    sampling: Sampling,
+) -> Result<FetchedPrices> {
+    let block_range = resolve_block_range(price_fetcher, fetch_range)?;
+    let sources = price_fetcher.source_descriptors();
+    let source_ranges = price_fetcher.effective_ranges(block_range.clone());
+    let prices = match sampling {
+        Sampling::EveryBlock => price_fetcher.fetch_prices(block_range.clone())?,
+        Sampling::Changed => price_fetcher.fetch_changed_prices(block_range.clone())?,
+        Sampling::EveryNBlocks(n) => {
+            let blocks = block_range.clone().step_by(n as usize).collect::<Vec<_>>();
+            price_fetcher.fetch_prices_at_blocks(&blocks)?
+        }
+        Sampling::Interval(interval) => {
+            let blocks = price_fetcher.interval_blocks(block_range.clone(), interval.num_seconds() as u64)?;
+            price_fetcher.fetch_prices_at_blocks(&blocks)?
+        }
+    };
+    let end_timestamp = (!block_range.is_empty())
+        .then(|| price_fetcher.block_timestamp(block_range.end - 1))
+        .transpose()?;
+    Ok(FetchedPrices { prices, sources, source_ranges, block_range, end_timestamp })
+}
+
+/// Blocks to fetch, checked against the latest block of the backend.
+fn resolve_block_range(price_fetcher: &PriceFetcher, fetch_range: FetchRange) -> Result<Range<u64>> {
+    match fetch_range {
+        FetchRange::Blocks { start, end } => {
+            let latest_block = price_fetcher.latest_block()?;
+            let end = end.unwrap_or(latest_block + 1);
+            if end > latest_block + 1 {
+                return Err(eyre!("Block range ends at {end}, past the latest block {latest_block}"));
+            }
+            if start >= end {
+                return Err(eyre!("Block range {start}..{end} is empty"));
+            }
+            Ok(start..end)
+        }
+        FetchRange::Times(time_range) => {
+            let block_range = price_fetcher.block_range_for_timestamps(
+                unix_timestamp(time_range.start)..unix_timestamp(time_range.end),
+            )?;
+            if block_range.is_empty() {
+                return Err(eyre!("No blocks between {} and {}", time_range.start, time_range.end));
+            }
+            println!(
+                "Resolved {}..{} to blocks {}..{}",
+                time_range.start, time_range.end, block_range.start, block_range.end,
+            );
+            Ok(block_range)
+        }
+    }
+}
+
+fn unix_timestamp(time: DateTime<Utc>) -> u64 {
+    time.timestamp().max(0) as u64
 }
 
 fn write_prices(prices: Vec<PriceFetcherResult>, write_dir: &PathBuf) -> Result<()> {
@@ -99,22 +231,31 @@ fn write_prices(prices: Vec<PriceFetcherResult>, write_dir: &PathBuf) -> Result<
     Ok(())
 }
 
-fn write_metadata(
-    chain_id: u64,
-    sources: Vec<String>,
-    precision: u8,
-    block_range: Range<u64>,
-    write_dir: &PathBuf,
-) -> Result<()> {
+fn write_candles(candles: Vec<Candle>, write_dir: &PathBuf) -> Result<()> {
+    if candles.is_empty() {
+        println!("No prices to aggregate into candles");
+        return Ok(());
+    }
+    let out_path = write_dir.join("candles.parquet");
+    pool_price_fetcher::writer::write_candles_to_parquet(&candles, &out_path)?;
+    println!("Candles written to: {}", out_path.display());
+    Ok(())
+}
+
+fn count_errors(prices: &[PriceFetcherResult]) -> BTreeMap<String, BTreeMap<PriceStatus, u64>> {
+    let mut counts = BTreeMap::<String, BTreeMap<PriceStatus, u64>>::new();
+    for price in prices.iter().filter(|price| price.status != PriceStatus::Ok) {
+        *counts
+            .entry(price.source.clone())
+            .or_default()
+            .entry(price.status)
+            .or_default() += 1;
+    }
+    counts
+}
+
+fn write_metadata(metadata: PricesMetadata, write_dir: &PathBuf) -> Result<()> {
     let out_path = write_dir.join("metadata.json");
-    let metadata = pool_price_fetcher::PricesMetadata {
-        chain_id: chain_id,
-        start_block: block_range.start,
-        end_block: block_range.end,
-        precision,
-        sources,
-    };
-    
     pool_price_fetcher::writer::write_prices_metadata(metadata, &out_path)?;
     println!("Metadata written to: {}", out_path.display());
     Ok(())
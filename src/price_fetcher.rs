@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 
@@ -11,18 +11,36 @@ use url::Url;
 
 use alloy::primitives::{Address, U256};
 use alloy::providers::RootProvider;
+use reth_ethereum::node::EthereumNode;
+use reth_op::node::OpNode;
 
-use crate::config::PriceSource;
-use crate::protocols::{self, BoxedProtocol, TokenInfo};
-use crate::reth_utils::{self, LocalProviderFactory};
+use crate::backend::{
+    ReadValue,
+    StateBackend,
+    RethBackend,
+    RpcBackend,
+    DEFAULT_RPC_BATCH_SIZE,
+    DEFAULT_RPC_MAX_CONCURRENCY,
+};
+use crate::config::{BackendType, NodeType, PriceSource};
+use crate::error::{PriceError, PriceStatus};
+use crate::reth_utils;
+use crate::protocols::{self, BoxedProtocol, DeploymentProbe, StateRead, TokenInfo, ViewCaller};
 
 
 #[derive(Default)]
 pub struct PriceFetcherBuilder {
     precision: u8,
+    chain_id: Option<u64>,
+    backend: BackendType,
     reth_db_path: Option<PathBuf>,
+    node_type: NodeType,
+    genesis_path: Option<PathBuf>,
     rpc_url: Option<Url>,
+    rpc_batch_size: Option<usize>,
+    rpc_max_concurrency: Option<usize>,
     price_sources: Option<Vec<PriceSource>>,
+    record_price_errors: bool,
 }
 
 impl PriceFetcherBuilder {
@@ -35,64 +53,169 @@ impl PriceFetcherBuilder {
         self
     }
 
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn backend(mut self, backend: BackendType) -> Self {
+        self.backend = backend;
+        self
+    }
+
     pub fn reth_db_path(mut self, path: impl AsRef<Path>) -> Self {
         self.reth_db_path = Some(path.as_ref().to_path_buf());
         self
     }
 
+    pub fn node_type(mut self, node_type: NodeType) -> Self {
+        self.node_type = node_type;
+        self
+    }
+
+    pub fn genesis_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.genesis_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     pub fn rpc_url(mut self, url: Url) -> Self {
         self.rpc_url = Some(url);
         self
     }
 
+    pub fn rpc_batch_size(mut self, batch_size: usize) -> Self {
+        self.rpc_batch_size = Some(batch_size);
+        self
+    }
+
+    pub fn rpc_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.rpc_max_concurrency = Some(max_concurrency);
+        self
+    }
+
     pub fn price_sources(mut self, sources: Vec<PriceSource>) -> Self {
         self.price_sources = Some(sources);
         self
     }
 
+    /// Emit (block, source) pairs that can't be priced with their `PriceError`
+    /// instead of failing the whole fetch.
+    pub fn record_price_errors(mut self, record: bool) -> Self {
+        self.record_price_errors = record;
+        self
+    }
+
     pub async fn build(self) -> Result<PriceFetcher> {
-        let reth_path = self.reth_db_path.ok_or_else(|| eyre!("reth_db_path not provided"))?;
-        let rpc_url = self.rpc_url.ok_or_else(|| eyre!("rpc_url not provided"))?;
         let price_sources = self.price_sources.ok_or_else(|| eyre!("price_sources not provided"))?;
 
-        let provider_factory = reth_utils::build_provider_factory(&reth_path)?;
-        let rpc_provider = RootProvider::new_http(rpc_url);
+        let backend: Box<dyn StateBackend> = match self.backend {
+            BackendType::Reth => {
+                let reth_path = self.reth_db_path.ok_or_else(|| eyre!("reth_db_path not provided"))?;
+                let chain_id = self.chain_id.ok_or_else(|| eyre!("chain_id not provided"))?;
+                let genesis_path = self.genesis_path.as_deref();
+                match self.node_type {
+                    NodeType::Ethereum => {
+                        let chain_spec = reth_utils::chain_spec(chain_id, genesis_path)?;
+                        Box::new(RethBackend::<EthereumNode>::new(&reth_path, chain_spec)?)
+                    }
+                    NodeType::Optimism => {
+                        let chain_spec = reth_utils::op_chain_spec(chain_id, genesis_path)?;
+                        Box::new(RethBackend::<OpNode>::new(&reth_path, chain_spec)?)
+                    }
+                }
+            }
+            BackendType::Rpc => {
+                let rpc_url = self.rpc_url.ok_or_else(|| eyre!("rpc_url not provided"))?;
+                Box::new(RpcBackend::new(
+                    RootProvider::new_http(rpc_url),
+                    self.rpc_batch_size.unwrap_or(DEFAULT_RPC_BATCH_SIZE),
+                    self.rpc_max_concurrency.unwrap_or(DEFAULT_RPC_MAX_CONCURRENCY),
+                ))
+            }
+        };
+        let view_caller = backend.view_caller()?;
 
-        let parsed_price_sources = Self::parse_price_sources(&rpc_provider, price_sources).await?;
-        let token_infos = Self::fetch_token_infos(&rpc_provider, &parsed_price_sources).await?;
+        let mut parsed_price_sources = Self::parse_price_sources(view_caller.as_ref(), price_sources).await?;
+        // the RPC backend blocks on this runtime, so the search can't run on its workers
+        let (backend, parsed_price_sources) = tokio::task::spawn_blocking(move || {
+            Self::find_deployment_blocks(backend.as_ref(), &mut parsed_price_sources)?;
+            Ok::<_, eyre::Report>((backend, parsed_price_sources))
+        }).await??;
+        let token_infos = Self::fetch_token_infos(view_caller.as_ref(), &parsed_price_sources).await?;
         let precision_factor = U256::from(10u64).pow(U256::from(self.precision));
 
         Ok(PriceFetcher {
             precision_factor,
+            record_price_errors: self.record_price_errors,
             token_infos,
             price_sources: parsed_price_sources,
-            provider_factory,
+            backend,
         })
     }
 
     async fn parse_price_sources(
-        provider: &RootProvider,
+        caller: &dyn ViewCaller,
         price_sources: Vec<PriceSource>,
     ) -> Result<Vec<ParsedPriceSource>> {
+        let price_sources = price_sources
+            .into_iter()
+            .filter(|source| source.enabled)
+            .collect::<Vec<_>>();
+        let mut names = HashSet::new();
+        if let Some(source) = price_sources.iter().find(|source| !names.insert(&source.name)) {
+            return Err(eyre!("Duplicate price source name {:?}", source.name));
+        }
+
         let futs = price_sources
             .into_iter()
             .map(|source| {
-                let provider = provider.clone();
                 async move {
-                    let protocol = source.protocol.into_boxed();
-                    let tokens = protocol.fetch_tokens(&provider).await?;
+                    let protocol_type = source.protocol.type_name();
+                    let mut protocol = source.protocol.into_boxed();
+                    protocol.prepare(caller).await?;
+                    let tokens = protocol.fetch_tokens(caller).await?;
+                    protocol.verify_layout(caller).await?;
                     Ok::<_, eyre::Report>(ParsedPriceSource {
+                        name: source.name,
+                        protocol_type,
                         inverse_it: source.inverse_it,
                         protocol,
                         tokens,
+                        active_blocks: source.start_block.unwrap_or(0)..source.end_block.unwrap_or(u64::MAX),
                     })
                 }
             });
         Ok(future::try_join_all(futs).await?)
     }
 
+    /// Moves each source's start past the block its last deployment probe
+    /// starts holding at, so blocks before it are not fetched.
+    fn find_deployment_blocks(
+        backend: &dyn StateBackend,
+        price_sources: &mut [ParsedPriceSource],
+    ) -> Result<()> {
+        let latest_block = backend.latest_block()?;
+        let deployment_blocks = price_sources
+            .iter()
+            .flat_map(|ps| ps.protocol.deployment_probes())
+            .collect::<HashSet<_>>()
+            .into_par_iter()
+            .map(|probe| Ok((probe.clone(), deployment_block(backend, &probe, latest_block)?)))
+            .collect::<Result<FxHashMap<_, _>>>()?;
+        for ps in price_sources {
+            let deployment_block = ps.protocol
+                .deployment_probes()
+                .iter()
+                .map(|probe| deployment_blocks[probe])
+                .max()
+                .unwrap_or(0);
+            ps.active_blocks.start = ps.active_blocks.start.max(deployment_block);
+        }
+        Ok(())
+    }
+
     async fn fetch_token_infos(
-        provider: &RootProvider,
+        caller: &dyn ViewCaller,
         price_sources: &[ParsedPriceSource],
     ) -> Result<FxHashMap<Address, TokenInfo>> {
         let futs = price_sources
@@ -101,9 +224,8 @@ impl PriceFetcherBuilder {
             .collect::<HashSet<_>>()
             .into_iter()
             .map(|&token| {
-                let provider = provider.clone();
                 async move {
-                    let info = protocols::fetch_token_info(&provider, token).await?;
+                    let info = protocols::fetch_token_info(caller, token).await?;
                     Ok::<_, eyre::Report>((token, info))
                 }
             });
@@ -111,18 +233,175 @@ impl PriceFetcherBuilder {
     }
 }
 
+/// Fetching methods block, and with the RPC backend they wait on the tokio
+/// runtime the fetcher was built in, so call them from outside its workers
+/// (e.g. in `spawn_blocking`).
 pub struct PriceFetcher {
     token_infos: FxHashMap<Address, TokenInfo>,
     price_sources: Vec<ParsedPriceSource>,
-    provider_factory: LocalProviderFactory,
+    backend: Box<dyn StateBackend>,
     precision_factor: U256,
+    record_price_errors: bool,
 }
 
 impl PriceFetcher {
     pub fn fetch_prices(&self, block_range: Range<u64>) -> Result<Vec<PriceFetcherResult>> {
-        Ok(block_range
+        self.fetch_prices_at_blocks(&block_range.collect::<Vec<_>>())
+    }
+
+    /// Like `fetch_prices`, but only at the given blocks.
+    pub fn fetch_prices_at_blocks(&self, blocks: &[u64]) -> Result<Vec<PriceFetcherResult>> {
+        let requests = blocks
+            .iter()
+            .map(|&block| (block, self.active_sources(block)))
+            .collect();
+        self.fetch_prices_in_chunks(requests)
+    }
+
+    /// Like `fetch_prices`, but a source is only priced at the first block of its
+    /// effective range and at blocks in which one of its storage reads changed. Sources
+    /// with call reads are priced at every block.
+    pub fn fetch_changed_prices(&self, block_range: Range<u64>) -> Result<Vec<PriceFetcherResult>> {
+        let source_change_blocks = self.price_sources
+            .par_iter()
+            .map(|ps| self.storage_change_blocks(ps, block_range.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut sources_by_block = BTreeMap::<u64, Vec<&ParsedPriceSource>>::new();
+        for (ps, change_blocks) in self.price_sources.iter().zip(source_change_blocks) {
+            for block in change_blocks {
+                sources_by_block.entry(block).or_default().push(ps);
+            }
+        }
+
+        self.fetch_prices_in_chunks(sources_by_block.into_iter().collect())
+    }
+
+    fn storage_change_blocks(
+        &self,
+        ps: &ParsedPriceSource,
+        block_range: Range<u64>,
+    ) -> Result<BTreeSet<u64>> {
+        let block_range = ps.effective_range(&block_range);
+        if block_range.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+        let mut blocks = BTreeSet::from([block_range.start]);
+        for read in ps.protocol.state_reads() {
+            match read {
+                StateRead::Storage { target, slot, .. } => {
+                    blocks.extend(self.backend.storage_change_blocks(target, slot, block_range.clone())?);
+                }
+                // computed values have no change history, so they can change in any block
+                StateRead::Call { .. } => return Ok(block_range.collect()),
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Prices of the sources deployed and configured to be fetched at `block_num`.
+    pub fn fetch_prices_for_block(&self, block_num: u64) -> Result<Vec<PriceFetcherResult>> {
+        self.fetch_prices_in_chunks(vec![(block_num, self.active_sources(block_num))])
+    }
+
+    fn active_sources(&self, block_num: u64) -> Vec<&ParsedPriceSource> {
+        self.price_sources
+            .iter()
+            .filter(|ps| ps.active_blocks.contains(&block_num))
+            .collect()
+    }
+
+    /// Most recent block the backend has state for.
+    pub fn latest_block(&self) -> Result<u64> {
+        self.backend.latest_block()
+    }
+
+    /// Timestamp of the block's header in unix seconds.
+    pub fn block_timestamp(&self, block_num: u64) -> Result<u64> {
+        header_timestamp(self.backend.as_ref(), block_num)
+    }
+
+    /// Blocks whose timestamps are within `timestamps`, a range of unix times.
+    pub fn block_range_for_timestamps(&self, timestamps: Range<u64>) -> Result<Range<u64>> {
+        let backend = self.backend.as_ref();
+        let latest_block = self.latest_block()?;
+        let (start, end) = rayon::join(
+            || first_block_at_timestamp(backend, timestamps.start, 0..latest_block + 1),
+            || first_block_at_timestamp(backend, timestamps.end, 0..latest_block + 1),
+        );
+        Ok(start?..end?)
+    }
+
+    /// Last block at or before each multiple of `interval` seconds since the
+    /// unix epoch that is within the timestamps of `block_range`.
+    pub fn interval_blocks(&self, block_range: Range<u64>, interval: u64) -> Result<Vec<u64>> {
+        if block_range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let backend = self.backend.as_ref();
+        let (first_timestamp, last_timestamp) = rayon::join(
+            || header_timestamp(backend, block_range.start),
+            || header_timestamp(backend, block_range.end - 1),
+        );
+        let boundaries = (first_timestamp?.div_ceil(interval)..=last_timestamp? / interval)
+            .map(|bucket| bucket * interval)
+            .collect::<Vec<_>>();
+        // a boundary is at or after the first block's timestamp, so the search
+        // for the first block past it never returns the range's start
+        let blocks = boundaries
             .into_par_iter()
-            .map(|block| self.fetch_prices_for_block(block))
+            .map(|boundary| Ok(first_block_at_timestamp(backend, boundary + 1, block_range.clone())? - 1))
+            .collect::<Result<BTreeSet<_>>>()?;
+        Ok(blocks.into_iter().collect())
+    }
+
+    /// Range each source is fetched over within `block_range`.
+    pub fn effective_ranges(&self, block_range: Range<u64>) -> Vec<SourceRange> {
+        self.price_sources
+            .iter()
+            .map(|ps| {
+                let range = ps.effective_range(&block_range);
+                SourceRange {
+                    source: ps.name.clone(),
+                    start_block: range.start,
+                    end_block: range.end,
+                }
+            })
+            .collect()
+    }
+
+    /// Configuration and token metadata of each fetched source.
+    pub fn source_descriptors(&self) -> Vec<SourceDescriptor> {
+        self.price_sources
+            .iter()
+            .map(|ps| {
+                let [base_token, quote_token] = ps.base_quote_tokens()
+                    .map(|token| TokenDescriptor::new(token, &self.token_infos[&token]));
+                SourceDescriptor {
+                    name: ps.name.clone(),
+                    protocol: ps.protocol_type.to_string(),
+                    pool_address: ps.protocol.pool_address(),
+                    inverse_it: ps.inverse_it,
+                    base_token,
+                    quote_token,
+                }
+            })
+            .collect()
+    }
+
+    /// Prices of the sources of each `(block, sources)` request, handing the
+    /// backend `blocks_per_chunk` blocks at a time so it can batch its reads.
+    fn fetch_prices_in_chunks(
+        &self,
+        requests: Vec<(u64, Vec<&ParsedPriceSource>)>,
+    ) -> Result<Vec<PriceFetcherResult>> {
+        let requests = requests
+            .into_iter()
+            .filter(|(_, sources)| !sources.is_empty())
+            .collect::<Vec<_>>();
+        Ok(requests
+            .par_chunks(self.backend.blocks_per_chunk())
+            .map(|chunk| self.fetch_prices_for_chunk(chunk))
             .collect::<Result<Vec<_>>>()?
             .into_iter()
             .flatten()
@@ -130,67 +409,461 @@ impl PriceFetcher {
         )
     }
 
-    pub fn fetch_prices_for_block(&self, block_num: u64) -> Result<Vec<PriceFetcherResult>> {
-        let block_timestamp = reth_utils::block_num_to_timestamp(&self.provider_factory, block_num)?;
-        let hist_provider = self.provider_factory.history_by_block_number(block_num)?;
-        self.price_sources
+    fn fetch_prices_for_chunk(
+        &self,
+        chunk: &[(u64, Vec<&ParsedPriceSource>)],
+    ) -> Result<Vec<PriceFetcherResult>> {
+        let blocks = chunk.iter().map(|(block_num, _)| *block_num).collect::<Vec<_>>();
+        let timestamps = self.backend.block_timestamps(&blocks)?;
+        let mut pending = chunk
             .iter()
-            .map(|ps| {
-                let slot = ps.protocol.storage_slot();
-                let target = ps.protocol.storage_target();
-                let storage = hist_provider
-                    .storage(target, slot)?
-                    .ok_or_else(|| eyre!("storage slot {slot} empty at block {block_num}"))?;
-
-                let price = ps.protocol.retrieve_price_from_storage(
-                    storage,
-                    ps.inverse_it,
-                    [
-                        self.token_infos[&ps.tokens[0]].dec_denom,
-                        self.token_infos[&ps.tokens[1]].dec_denom,
-                    ],
-                    self.precision_factor,
-                )?;
-
-                let (base_token, quote_token) =
-                    if ps.inverse_it {
-                        (ps.tokens[1], ps.tokens[0])
-                    } else {
-                        (ps.tokens[0], ps.tokens[1])
-                    };
-                Ok(PriceFetcherResult {
-                    block_num,
+            .zip(timestamps)
+            .flat_map(|((block_num, sources), block_timestamp)| {
+                sources.iter().map(move |&ps| PendingPrice {
+                    block_num: *block_num,
                     block_timestamp,
-                    source: ps.protocol.name(),
-                    price,
-                    quote_token,
-                    base_token,
+                    ps,
+                    storage: match block_timestamp {
+                        Some(_) => Ok(Vec::new()),
+                        None => Err(PriceError::HeaderMissing),
+                    },
                 })
             })
+            .collect::<Vec<_>>();
+        // dependent reads are only known once the state reads are in
+        self.read_pending(&mut pending, |ps, _| ps.protocol.state_reads())?;
+        self.read_pending(&mut pending, |ps, storage| ps.protocol.dependent_reads(storage))?;
+
+        pending
+            .into_iter()
+            .map(|pending| self.price_result(pending))
             .collect()
     }
+
+    /// Appends the values of `reads` to the storage of every pending price that
+    /// hasn't failed yet, with a single backend request for all of them.
+    fn read_pending(
+        &self,
+        pending: &mut [PendingPrice],
+        reads: impl Fn(&ParsedPriceSource, &[U256]) -> Vec<StateRead>,
+    ) -> Result<()> {
+        let (indices, requests): (Vec<_>, Vec<_>) = pending
+            .iter()
+            .enumerate()
+            .filter_map(|(k, pending)| {
+                let reads = reads(pending.ps, pending.storage.as_ref().ok()?);
+                (!reads.is_empty()).then_some((k, (pending.block_num, reads)))
+            })
+            .unzip();
+        if requests.is_empty() {
+            return Ok(());
+        }
+        let values = self.backend.read_states(&requests)?;
+        for ((k, (block_num, reads)), values) in indices.into_iter().zip(requests).zip(values) {
+            let pending = &mut pending[k];
+            match self.resolve_reads(block_num, reads, values)? {
+                Ok(values) => {
+                    if let Ok(storage) = &mut pending.storage {
+                        storage.extend(values);
+                    }
+                }
+                Err(error) => pending.storage = Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Values read for `reads` at a block. A failed call or an empty required
+    /// slot is an error, `NotDeployed` when its contract has no code yet.
+    fn resolve_reads(
+        &self,
+        block_num: u64,
+        reads: Vec<StateRead>,
+        values: Vec<ReadValue>,
+    ) -> Result<Result<Vec<U256>, PriceError>> {
+        let mut storage = Vec::with_capacity(values.len());
+        for (read, value) in reads.into_iter().zip(values) {
+            let value = match (value, read) {
+                (Err(error), _) => return Ok(Err(error)),
+                (Ok(Some(value)), _) => value,
+                (Ok(None), StateRead::Storage { optional: true, .. }) => U256::ZERO,
+                (Ok(None), StateRead::Storage { target, slot, .. }) => {
+                    if self.backend.has_code(target, block_num)? {
+                        return Ok(Err(PriceError::EmptySlot { target, slot }));
+                    }
+                    return Ok(Err(PriceError::NotDeployed { target }));
+                }
+                (Ok(None), StateRead::Call { target, .. }) => return Ok(Err(PriceError::CallFailed {
+                    target,
+                    reason: "no value".to_string(),
+                })),
+            };
+            storage.push(value);
+        }
+        Ok(Ok(storage))
+    }
+
+    /// Row of a price whose reads are all in. Fails when prices that can't be
+    /// computed are not recorded.
+    fn price_result(&self, pending: PendingPrice) -> Result<PriceFetcherResult> {
+        let PendingPrice { block_num, block_timestamp, ps, storage } = pending;
+        let price = storage.and_then(|storage| ps.protocol.retrieve_price_from_storage(
+            &storage,
+            ps.inverse_it,
+            [
+                self.token_infos[&ps.tokens[0]].dec_denom,
+                self.token_infos[&ps.tokens[1]].dec_denom,
+            ],
+            self.precision_factor,
+        ));
+        if let Err(error) = &price {
+            if !self.record_price_errors {
+                return Err(eyre!("{} at block {block_num}: {error}", ps.name));
+            }
+        }
+
+        let [base_token, quote_token] = ps.base_quote_tokens();
+        Ok(PriceFetcherResult {
+            block_num,
+            block_timestamp,
+            source: ps.name.clone(),
+            protocol: ps.protocol_type.to_string(),
+            pool_address: ps.protocol.pool_address(),
+            price: price.as_ref().ok().copied(),
+            quote_token,
+            base_token,
+            status: price.as_ref().map_or_else(PriceError::status, |_| PriceStatus::Ok),
+            error: price.err(),
+        })
+    }
+}
+
+/// Price of a source at a block whose reads are still coming in.
+struct PendingPrice<'a> {
+    block_num: u64,
+    block_timestamp: Option<u64>,
+    ps: &'a ParsedPriceSource,
+    /// Values read so far, or why the source can't be priced.
+    storage: Result<Vec<U256>, PriceError>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceFetcherResult {
     pub block_num: u64,
-    pub block_timestamp: u64,
+    /// `None` when the block's header is missing.
+    pub block_timestamp: Option<u64>,
+    /// Configured name of the source.
     pub source: String,
+    /// Protocol `type` of the source, e.g. `univ3`.
+    pub protocol: String,
+    pub pool_address: Address,
+    /// `None` when the source could not be priced, see `error`.
     #[serde(serialize_with = "serialize_u256_to_dec")]
-    pub price: U256,
+    pub price: Option<U256>,
     pub quote_token: Address,
     pub base_token: Address,
+    pub status: PriceStatus,
+    #[serde(skip_deserializing, serialize_with = "serialize_error")]
+    pub error: Option<PriceError>,
 }
 
 struct ParsedPriceSource {
+    name: String,
+    protocol_type: &'static str,
     inverse_it: bool,
     protocol: BoxedProtocol,
     tokens: [Address; 2],
+    /// Blocks the source is configured for, starting no earlier than its deployment.
+    active_blocks: Range<u64>,
+}
+
+impl ParsedPriceSource {
+
+    /// Part of `block_range` in which the source is active, possibly empty.
+    fn effective_range(&self, block_range: &Range<u64>) -> Range<u64> {
+        let end = block_range.end.min(self.active_blocks.end);
+        block_range.start.max(self.active_blocks.start).min(end)..end
+    }
+
+    /// Tokens the price is of and in, after applying `inverse_it`.
+    fn base_quote_tokens(&self) -> [Address; 2] {
+        if self.inverse_it {
+            [self.tokens[1], self.tokens[0]]
+        } else {
+            self.tokens
+        }
+    }
+
 }
 
-fn serialize_u256_to_dec<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
+/// Blocks a source was priced over, after clipping to its deployment and
+/// configured range.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceRange {
+    pub source: String,
+    pub start_block: u64,
+    pub end_block: u64,
+}
+
+/// A fetched source as listed in the metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceDescriptor {
+    pub name: String,
+    pub protocol: String,
+    pub pool_address: Address,
+    pub inverse_it: bool,
+    pub base_token: TokenDescriptor,
+    pub quote_token: TokenDescriptor,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenDescriptor {
+    pub address: Address,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenDescriptor {
+    fn new(address: Address, info: &TokenInfo) -> Self {
+        Self {
+            address,
+            symbol: info.symbol.clone(),
+            decimals: info.decimals,
+        }
+    }
+}
+
+/// First block at whose end `probe` holds, by binary search over the
+/// history. Contracts that were later self-destructed are not supported.
+fn deployment_block(backend: &dyn StateBackend, probe: &DeploymentProbe, latest_block: u64) -> Result<u64> {
+    if !probe_holds(backend, probe, latest_block)? {
+        return Err(eyre!("No {probe} at block {latest_block}"));
+    }
+    let (mut low, mut high) = (0, latest_block);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if probe_holds(backend, probe, mid)? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(low)
+}
+
+fn probe_holds(backend: &dyn StateBackend, probe: &DeploymentProbe, block_num: u64) -> Result<bool> {
+    match probe {
+        DeploymentProbe::Code(address) => backend.has_code(*address, block_num),
+        DeploymentProbe::Storage { target, slot } => {
+            let values = backend.read_state(block_num, &[StateRead::storage(*target, *slot)])?;
+            Ok(matches!(values[..], [Ok(Some(_))]))
+        }
+    }
+}
+
+/// First block in `blocks` with a timestamp of at least `timestamp`, or the
+/// end of `blocks` if there is none.
+fn first_block_at_timestamp(backend: &dyn StateBackend, timestamp: u64, blocks: Range<u64>) -> Result<u64> {
+    let (mut low, mut high) = (blocks.start, blocks.end);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if header_timestamp(backend, mid)? >= timestamp {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(low)
+}
+
+fn header_timestamp(backend: &dyn StateBackend, block_num: u64) -> Result<u64> {
+    backend
+        .block_timestamp(block_num)?
+        .ok_or_else(|| eyre!("Header of block {block_num} not found"))
+}
+
+fn serialize_error<S>(error: &Option<PriceError>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    serializer.serialize_str(&value.to_string())
+    match error {
+        Some(error) => serializer.serialize_some(&error.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_u256_to_dec<S>(value: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_some(&value.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, B256, Bytes, U256, address};
+    use eyre::{Result, eyre};
+    use fxhash::FxHashMap;
+    use crate::backend::{ReadValue, StateBackend};
+    use crate::config::ProtocolType;
+    use crate::error::PriceError;
+    use crate::protocols::{BoxedProtocol, StateRead, ViewCaller};
+    use super::{ParsedPriceSource, PriceFetcherBuilder};
+
+    const POOL_MANAGER: Address = address!("000000000004444c5dc75cB358380D2e3dE08A90");
+    const BALANCER_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+    const BALANCER_POOL: Address = address!("5c6Ee304399DBdB9C8Ef030aB642B10820DB8F56");
+    const UNIV2_PAIR: Address = address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+
+    /// History in which each contract's code and each slot appear at a fixed block.
+    #[derive(Default)]
+    struct MockBackend {
+        code_blocks: FxHashMap<Address, u64>,
+        slot_blocks: FxHashMap<(Address, B256), u64>,
+    }
+
+    impl StateBackend for MockBackend {
+
+        fn latest_block(&self) -> Result<u64> {
+            Ok(1_000)
+        }
+
+        fn block_timestamp(&self, block_num: u64) -> Result<Option<u64>> {
+            Ok(Some(block_num * 12))
+        }
+
+        fn read_state(&self, block_num: u64, reads: &[StateRead]) -> Result<Vec<ReadValue>> {
+            Ok(reads
+                .iter()
+                .map(|read| match read {
+                    StateRead::Storage { target, slot, .. } => Ok(self.slot_blocks
+                        .get(&(*target, *slot))
+                        .filter(|&&from| block_num >= from)
+                        .map(|_| U256::from(1u8))),
+                    StateRead::Call { target, .. } => Err(PriceError::CallFailed {
+                        target: *target,
+                        reason: "no calls".to_string(),
+                    }),
+                })
+                .collect())
+        }
+
+        fn has_code(&self, address: Address, block_num: u64) -> Result<bool> {
+            Ok(self.code_blocks.get(&address).is_some_and(|&from| block_num >= from))
+        }
+
+        fn storage_change_blocks(&self, _address: Address, _slot: B256, _block_range: std::ops::Range<u64>) -> Result<Vec<u64>> {
+            Err(eyre!("no change history"))
+        }
+
+        fn view_caller(&self) -> Result<Box<dyn ViewCaller>> {
+            Err(eyre!("no view calls"))
+        }
+
+    }
+
+    struct NoViewCaller;
+
+    #[async_trait::async_trait]
+    impl ViewCaller for NoViewCaller {
+
+        async fn call(&self, to: Address, _input: Bytes) -> Result<Bytes> {
+            Err(eyre!("unexpected call to {to}"))
+        }
+
+        async fn storage(&self, address: Address, _slot: B256) -> Result<U256> {
+            Err(eyre!("unexpected storage read of {address}"))
+        }
+
+    }
+
+    fn unprepared_protocol(config: &str) -> BoxedProtocol {
+        let protocol_type: ProtocolType = toml::from_str(config).unwrap();
+        protocol_type.into_boxed()
+    }
+
+    /// For protocols whose `prepare` needs no calls.
+    fn protocol(config: &str) -> BoxedProtocol {
+        let mut protocol = unprepared_protocol(config);
+        futures::executor::block_on(protocol.prepare(&NoViewCaller)).unwrap();
+        protocol
+    }
+
+    fn source(name: &str, protocol: BoxedProtocol, start_block: u64) -> ParsedPriceSource {
+        ParsedPriceSource {
+            name: name.to_string(),
+            protocol_type: "test",
+            inverse_it: false,
+            protocol,
+            tokens: [Address::ZERO; 2],
+            active_blocks: start_block..u64::MAX,
+        }
+    }
+
+    /// Pools inside a singleton start where they were created, not where the
+    /// singleton was deployed.
+    #[test]
+    fn singleton_pools_start_at_their_creation() {
+        let univ4 = protocol(&format!(r#"
+            type = "univ4"
+            pool_manager = "{POOL_MANAGER}"
+            currency0 = "0x0000000000000000000000000000000000000000"
+            currency1 = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+            fee = 500
+            tick_spacing = 10
+        "#));
+        // the probe only needs the pool id, while `prepare` would call the Vault
+        let balancer = unprepared_protocol(&format!(r#"
+            type = "balancerv2"
+            vault = "{BALANCER_VAULT}"
+            pool_id = "0x5c6ee304399dbdb9c8ef030ab642b10820db8f56000200000000000000000014"
+        "#));
+        let univ2 = protocol(&format!(r#"
+            type = "univ2"
+            pool = "{UNIV2_PAIR}"
+        "#));
+        let StateRead::Storage { slot: slot0_slot, .. } = univ4.state_reads()[0] else {
+            panic!("UniV4 reads Slot0 from storage");
+        };
+
+        let backend = MockBackend {
+            code_blocks: FxHashMap::from_iter([
+                (POOL_MANAGER, 100),
+                (BALANCER_VAULT, 50),
+                (BALANCER_POOL, 400),
+                (UNIV2_PAIR, 200),
+            ]),
+            slot_blocks: FxHashMap::from_iter([((POOL_MANAGER, slot0_slot), 700)]),
+        };
+        let mut sources = vec![
+            source("univ4", univ4, 0),
+            source("balancer", balancer, 0),
+            source("univ2", univ2, 0),
+            source("univ2_configured_later", protocol(&format!("type = \"univ2\"\npool = \"{UNIV2_PAIR}\"")), 300),
+        ];
+        PriceFetcherBuilder::find_deployment_blocks(&backend, &mut sources).unwrap();
+
+        let starts = sources.iter().map(|ps| ps.active_blocks.start).collect::<Vec<_>>();
+        assert_eq!(starts, [700, 400, 200, 300]);
+    }
+
+    #[test]
+    fn uninitialized_singleton_pool_is_an_error() {
+        let univ4 = protocol(&format!(r#"
+            type = "univ4"
+            pool_manager = "{POOL_MANAGER}"
+            currency0 = "0x0000000000000000000000000000000000000000"
+            currency1 = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+            pool_id = "0x21c67e77068de97969ba93d4aab21826d33ca12bb9f565d8496e8fda8a82ca27"
+        "#));
+        let backend = MockBackend {
+            code_blocks: FxHashMap::from_iter([(POOL_MANAGER, 100)]),
+            ..Default::default()
+        };
+        let mut sources = vec![source("univ4", univ4, 0)];
+        assert!(PriceFetcherBuilder::find_deployment_blocks(&backend, &mut sources).is_err());
+    }
 }
@@ -0,0 +1,77 @@
+use alloy::primitives::{Address, B256, U256};
+use alloy::sol;
+use eyre::Result;
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+use super::univ3::{sqrt_price_x96_to_price, U160_MASK};
+
+
+sol!{
+    interface IAlgebraPool {
+        // only the leading field, which Algebra versions agree on
+        function globalState() external view returns (uint160 price);
+    }
+}
+
+/// Algebra concentrated liquidity pool (Camelot, QuickSwap V3).
+///
+/// `globalState.price` is a UniV3-style sqrtPriceX96 in the low 160 bits of
+/// `global_state_slot`, which follows `totalFeeGrowth0Token`/`totalFeeGrowth1Token`
+/// in Algebra V1 pools.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Algebra {
+    pool: Address,
+    #[serde(default = "default_global_state_slot")]
+    global_state_slot: u64,
+}
+
+fn default_global_state_slot() -> u64 {
+    2
+}
+
+impl Algebra {
+
+    fn global_state_slot(&self) -> B256 {
+        B256::from(U256::from(self.global_state_slot))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Protocol for Algebra {
+    fn name(&self) -> String {
+        format!("Algebra: {}", self.pool)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.pool
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::storage(self.pool, self.global_state_slot())]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let sqrt_price_x96 = storage[0] & U160_MASK;
+        sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, precision_factor)
+    }
+
+    async fn fetch_tokens(
+        &self,
+        caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        common::uniswap_pool_tokens(caller, self.pool).await
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let global_state_word = caller.storage(self.pool, self.global_state_slot()).await?;
+        let price = common::view_call(caller, self.pool, IAlgebraPool::globalStateCall {}).await?;
+        common::check_layout(&self.name(), global_state_word & U160_MASK, U256::from(price))
+    }
+}
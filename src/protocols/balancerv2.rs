@@ -0,0 +1,249 @@
+use alloy::primitives::{Address, B256, U256, keccak256, b256};
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use eyre::{Result, eyre};
+use crate::error::PriceError;
+use super::common::{self, DeploymentProbe, Protocol, StateRead, ViewCaller};
+use super::univ2::U112_MASK;
+
+
+const GENERAL_POOLS_BALANCES_SLOT: B256 = b256!("0000000000000000000000000000000000000000000000000000000000000006");
+const MINIMAL_SWAP_INFO_POOLS_BALANCES_SLOT: B256 = b256!("0000000000000000000000000000000000000000000000000000000000000007");
+const TWO_TOKEN_POOL_TOKENS_SLOT: B256 = b256!("0000000000000000000000000000000000000000000000000000000000000009");
+
+sol!{
+    interface IBalancerVault {
+        function getPoolTokens(bytes32 poolId) external view returns (
+            address[] tokens,
+            uint256[] balances,
+            uint256 lastChangeBlock
+        );
+    }
+
+    interface IWeightedPool {
+        function getNormalizedWeights() external view returns (uint256[]);
+    }
+
+    interface IGradualWeightPool {
+        function getGradualWeightUpdateParams() external view;
+    }
+}
+
+/// Weighted pool whose balances live in the Balancer V2 Vault.
+///
+/// Without `base_token`/`quote_token` the pool's first two tokens are used.
+/// Weights are read once, so only fixed-weight pools are supported; pools
+/// with gradual weight updates (LBPs, managed pools) are rejected.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct BalancerV2 {
+    vault: Address,
+    pool_id: B256,
+    base_token: Option<Address>,
+    quote_token: Option<Address>,
+    #[serde(skip)]
+    pool_state: Option<PoolState>,
+}
+
+#[derive(Debug, Clone)]
+struct PoolState {
+    tokens: Vec<Address>,
+    weights: Vec<U256>,
+    base_index: usize,
+    quote_index: usize,
+}
+
+/// How the Vault stores the pool's balances, encoded in bytes 20..22 of the poolId.
+#[derive(Debug, Clone, Copy)]
+enum Specialization {
+    General,
+    MinimalSwapInfo,
+    TwoToken,
+}
+
+impl BalancerV2 {
+
+    fn specialization(&self) -> Result<Specialization> {
+        match u16::from_be_bytes([self.pool_id[20], self.pool_id[21]]) {
+            0 => Ok(Specialization::General),
+            1 => Ok(Specialization::MinimalSwapInfo),
+            2 => Ok(Specialization::TwoToken),
+            other => Err(eyre!("Unknown Balancer pool specialization {other}")),
+        }
+    }
+
+    fn pool_state(&self) -> &PoolState {
+        self.pool_state.as_ref().expect("BalancerV2 pool state not prepared")
+    }
+
+    /// Base and quote balances (cash + managed) decoded from `state_reads`.
+    fn balances(&self, storage: &[U256]) -> [U256; 2] {
+        let state = self.pool_state();
+        match self.specialization().expect("specialization checked in fetch_tokens") {
+            Specialization::General | Specialization::MinimalSwapInfo => {
+                [total_balance(storage[0]), total_balance(storage[1])]
+            }
+            Specialization::TwoToken => {
+                let (shared_cash, shared_managed) = (storage[0], storage[1]);
+                let balance_a = (shared_cash & U112_MASK) + (shared_managed & U112_MASK);
+                let balance_b = (shared_cash >> 112 & U112_MASK) + (shared_managed >> 112 & U112_MASK);
+                if state.tokens[state.base_index] < state.tokens[state.quote_index] {
+                    [balance_a, balance_b]
+                } else {
+                    [balance_b, balance_a]
+                }
+            }
+        }
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Protocol for BalancerV2 {
+    fn name(&self) -> String {
+        format!("BalancerV2: {}", self.pool_id)
+    }
+
+    fn pool_address(&self) -> Address {
+        Address::from_slice(&self.pool_id[..20])
+    }
+
+    async fn prepare(&mut self, caller: &dyn ViewCaller) -> Result<()> {
+        let tokens = common::view_call(
+            caller,
+            self.vault,
+            IBalancerVault::getPoolTokensCall { poolId: self.pool_id },
+        ).await?.tokens;
+        let weights = common::view_call(
+            caller,
+            self.pool_address(),
+            IWeightedPool::getNormalizedWeightsCall {},
+        ).await?;
+
+        let index_of = |token: Option<Address>, default: usize| match token {
+            Some(token) => tokens
+                .iter()
+                .position(|t| *t == token)
+                .ok_or_else(|| eyre!("Token {token} not in Balancer pool {}", self.pool_id)),
+            None => Ok(default),
+        };
+        let base_index = index_of(self.base_token, 0)?;
+        let quote_index = index_of(self.quote_token, 1)?;
+        if base_index == quote_index {
+            return Err(eyre!("Base and quote token of Balancer pool {} are both {}", self.pool_id, tokens[base_index]));
+        }
+        // only pools whose weights can change over time have this getter
+        let weight_update_call = IGradualWeightPool::getGradualWeightUpdateParamsCall {}.abi_encode();
+        if caller.call(self.pool_address(), weight_update_call.into()).await.is_ok() {
+            return Err(eyre!("Balancer pool {} has changing weights, only fixed-weight pools are supported", self.pool_id));
+        }
+
+        self.pool_state = Some(PoolState { tokens, weights, base_index, quote_index });
+        Ok(())
+    }
+
+    /// Pools register with the Vault when they are deployed.
+    fn deployment_probes(&self) -> Vec<DeploymentProbe> {
+        vec![DeploymentProbe::Code(self.pool_address())]
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        let state = self.pool_state();
+        let pair = [state.base_index, state.quote_index];
+        match self.specialization().expect("specialization checked in fetch_tokens") {
+            Specialization::General => {
+                // EnumerableMap { _length, _entries, _indexes }; entry values follow their keys
+                let map_slot = common::mapping_slot(self.pool_id, GENERAL_POOLS_BALANCES_SLOT);
+                let entries_slot = common::slot_offset(map_slot, 1);
+                pair.into_iter()
+                    .map(|index| {
+                        let entry_slot = common::mapping_slot(B256::from(U256::from(index)), entries_slot);
+                        StateRead::storage(self.vault, common::slot_offset(entry_slot, 1))
+                    })
+                    .collect()
+            }
+            Specialization::MinimalSwapInfo => {
+                let pool_balances_slot = common::mapping_slot(self.pool_id, MINIMAL_SWAP_INFO_POOLS_BALANCES_SLOT);
+                pair.into_iter()
+                    .map(|index| {
+                        let token = state.tokens[index].into_word();
+                        StateRead::storage(self.vault, common::mapping_slot(token, pool_balances_slot))
+                    })
+                    .collect()
+            }
+            Specialization::TwoToken => {
+                // TwoTokenPoolTokens { tokenA, tokenB, balances[pairHash] -> { sharedCash, sharedManaged } }
+                let pool_tokens_slot = common::mapping_slot(self.pool_id, TWO_TOKEN_POOL_TOKENS_SLOT);
+                let (token_a, token_b) = sorted_pair(state);
+                let pair_hash = keccak256([token_a.as_slice(), token_b.as_slice()].concat());
+                let balances_slot = common::mapping_slot(pair_hash, common::slot_offset(pool_tokens_slot, 2));
+                vec![
+                    StateRead::storage(self.vault, balances_slot),
+                    StateRead::optional_storage(self.vault, common::slot_offset(balances_slot, 1)),
+                ]
+            }
+        }
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let state = self.pool_state();
+        let [base_balance, quote_balance] = self.balances(storage);
+        let base_weight = state.weights[state.base_index];
+        let quote_weight = state.weights[state.quote_index];
+
+        // (B_quote / W_quote) / (B_base / W_base), with balances scaled to whole tokens
+        if inverse_it {
+            common::mul_div(
+                &[precision_factor, base_balance, quote_weight, dec_denoms[1]],
+                &[quote_balance, base_weight, dec_denoms[0]],
+            )
+        } else {
+            common::mul_div(
+                &[precision_factor, quote_balance, base_weight, dec_denoms[0]],
+                &[base_balance, quote_weight, dec_denoms[1]],
+            )
+        }
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        self.specialization()?;
+        let state = self.pool_state
+            .as_ref()
+            .ok_or_else(|| eyre!("BalancerV2 pool {} not prepared", self.pool_id))?;
+        Ok([state.tokens[state.base_index], state.tokens[state.quote_index]])
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let state = self.pool_state();
+        let storage = common::read_state(caller, &self.state_reads()).await?;
+        let balances = common::view_call(
+            caller,
+            self.vault,
+            IBalancerVault::getPoolTokensCall { poolId: self.pool_id },
+        ).await?.balances;
+        common::check_layout(
+            &self.name(),
+            self.balances(&storage),
+            [balances[state.base_index], balances[state.quote_index]],
+        )
+    }
+}
+
+/// Packed Vault balance: `[ 32 bits last change block | 112 bits managed | 112 bits cash ]`.
+fn total_balance(balance: U256) -> U256 {
+    (balance & U112_MASK) + (balance >> 112 & U112_MASK)
+}
+
+fn sorted_pair(state: &PoolState) -> (Address, Address) {
+    let base = state.tokens[state.base_index];
+    let quote = state.tokens[state.quote_index];
+    if base < quote { (base, quote) } else { (quote, base) }
+}
@@ -0,0 +1,234 @@
+use alloy::primitives::{Address, B256, U256, uint};
+use alloy::sol;
+use eyre::Result;
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+
+
+const U16_MASK: U256 = uint!(65535_U256);
+const U32_MASK: U256 = uint!(4294967295_U256);
+const U192_MASK: U256 = uint!(6277101735386680763835789423207666416102355444464034512895_U256);
+const INT192_SIGN_BIT: usize = 191;
+const INT256_SIGN_BIT: usize = 255;
+const LATEST_ROUND_ID_OFFSET: usize = 176;
+const PHASE_AGGREGATOR_OFFSET: usize = 16;
+
+sol!{
+    interface IAggregatorProxy {
+        function aggregator() external view returns (address);
+        function phaseId() external view returns (uint16);
+        function phaseAggregators(uint16 phaseId) external view returns (address);
+        function decimals() external view returns (uint8);
+        function latestAnswer() external view returns (int256);
+    }
+
+    interface IAggregator {
+        function latestAnswer() external view returns (int256);
+    }
+}
+
+/// Chainlink feed read through its EACAggregatorProxy.
+///
+/// The aggregator is looked up per block in the proxy's `currentPhase`, so
+/// ranges spanning several phases are priced from the aggregator that was
+/// live at each block. OCR aggregators are read from storage; aggregators of
+/// older phases whose storage doesn't match the OCR layout are priced through
+/// the proxy's `latestAnswer()` instead. Feeds are not pools, hence
+/// `base_token` and `quote_token` come from config (Chainlink denominations
+/// such as USD `0x0000000000000000000000000000000000000348` are accepted).
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Chainlink {
+    proxy: Address,
+    base_token: Address,
+    quote_token: Address,
+    #[serde(default = "default_current_phase_slot")]
+    current_phase_slot: u64,
+    #[serde(default = "default_hot_vars_slot")]
+    hot_vars_slot: u64,
+    #[serde(default = "default_transmissions_slot")]
+    transmissions_slot: u64,
+    #[serde(skip)]
+    feed: Option<Feed>,
+}
+
+#[derive(Debug, Clone)]
+struct Feed {
+    dec_denom: U256,
+    /// Aggregators of all phases up to the current one.
+    phases: Vec<Phase>,
+}
+
+#[derive(Debug, Clone)]
+struct Phase {
+    aggregator: Address,
+    /// Whether `hotVars` and `transmissions` are at the configured slots.
+    ocr_layout: bool,
+}
+
+fn default_current_phase_slot() -> u64 {
+    2
+}
+
+fn default_hot_vars_slot() -> u64 {
+    43
+}
+
+fn default_transmissions_slot() -> u64 {
+    44
+}
+
+impl Chainlink {
+
+    fn feed(&self) -> &Feed {
+        self.feed.as_ref().expect("Chainlink feed not prepared")
+    }
+
+    fn ocr_aggregators(&self) -> impl Iterator<Item = Address> + '_ {
+        self.feed()
+            .phases
+            .iter()
+            .filter(|phase| phase.ocr_layout)
+            .map(|phase| phase.aggregator)
+    }
+
+    fn has_legacy_phases(&self) -> bool {
+        self.feed().phases.iter().any(|phase| !phase.ocr_layout)
+    }
+
+    fn hot_vars_slot(&self) -> B256 {
+        B256::from(U256::from(self.hot_vars_slot))
+    }
+
+    fn transmission_slot(&self, hot_vars: U256) -> B256 {
+        // HotVars { bytes16 latestConfigDigest; uint40 latestEpochAndRound; uint8 threshold; uint32 latestAggregatorRoundId }
+        let round_id = hot_vars >> LATEST_ROUND_ID_OFFSET & U32_MASK;
+        common::mapping_slot(B256::from(round_id), B256::from(U256::from(self.transmissions_slot)))
+    }
+
+    /// Whether the answer decoded from `aggregator`'s storage matches its
+    /// `latestAnswer()`.
+    async fn has_ocr_layout(&self, caller: &dyn ViewCaller, aggregator: Address) -> Result<bool> {
+        let hot_vars = caller.storage(aggregator, self.hot_vars_slot()).await?;
+        let transmission = caller.storage(aggregator, self.transmission_slot(hot_vars)).await?;
+        let latest_answer = common::view_call(caller, aggregator, IAggregator::latestAnswerCall {}).await;
+        Ok(latest_answer.is_ok_and(|answer| transmission & U192_MASK == answer.into_raw() & U192_MASK))
+    }
+
+}
+
+/// Aggregator of the `Phase { uint16 id; address aggregator }` in the proxy's
+/// `currentPhase` slot.
+fn phase_aggregator(current_phase: U256) -> Address {
+    Address::from_word(B256::from(current_phase >> PHASE_AGGREGATOR_OFFSET << 96 >> 96))
+}
+
+#[async_trait::async_trait]
+impl Protocol for Chainlink {
+    fn name(&self) -> String {
+        format!("Chainlink: {}", self.proxy)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.proxy
+    }
+
+    async fn prepare(&mut self, caller: &dyn ViewCaller) -> Result<()> {
+        let decimals = common::view_call(caller, self.proxy, IAggregatorProxy::decimalsCall {}).await?;
+        let dec_denom = U256::from(10u64).pow(U256::from(decimals));
+        let current_phase_id = common::view_call(caller, self.proxy, IAggregatorProxy::phaseIdCall {}).await?;
+        let mut phases = Vec::new();
+        for phase_id in 1..=current_phase_id {
+            let aggregator = common::view_call(
+                caller,
+                self.proxy,
+                IAggregatorProxy::phaseAggregatorsCall { phaseId: phase_id },
+            ).await?;
+            if aggregator.is_zero() || phases.iter().any(|phase: &Phase| phase.aggregator == aggregator) {
+                continue;
+            }
+            let ocr_layout = self.has_ocr_layout(caller, aggregator).await?;
+            phases.push(Phase { aggregator, ocr_layout });
+        }
+        self.feed = Some(Feed { dec_denom, phases });
+        Ok(())
+    }
+
+    /// The proxy's `currentPhase`, then `hotVars` of every OCR aggregator
+    /// (optional, as later ones are not deployed yet at early blocks), then
+    /// the proxy's answer if some phase is priced through it.
+    fn state_reads(&self) -> Vec<StateRead> {
+        let mut reads = vec![StateRead::storage(self.proxy, B256::from(U256::from(self.current_phase_slot)))];
+        reads.extend(
+            self.ocr_aggregators()
+                .map(|aggregator| StateRead::optional_storage(aggregator, self.hot_vars_slot()))
+        );
+        if self.has_legacy_phases() {
+            reads.push(StateRead::call(self.proxy, IAggregatorProxy::latestAnswerCall {}));
+        }
+        reads
+    }
+
+    fn dependent_reads(&self, storage: &[U256]) -> Vec<StateRead> {
+        let aggregator = phase_aggregator(storage[0]);
+        match self.ocr_aggregators().position(|ocr_aggregator| ocr_aggregator == aggregator) {
+            Some(k) => vec![StateRead::storage(aggregator, self.transmission_slot(storage[1 + k]))],
+            None => Vec::new(),
+        }
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        _dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let aggregator = phase_aggregator(storage[0]);
+        let phase = self.feed()
+            .phases
+            .iter()
+            .find(|phase| phase.aggregator == aggregator)
+            .ok_or_else(|| PriceError::InvalidState(format!(
+                "aggregator {aggregator} of Chainlink feed {} is not one of its phases",
+                self.proxy,
+            )))?;
+        let (answer, sign_bit) = if phase.ocr_layout {
+            (transmitted_answer(storage), INT192_SIGN_BIT)
+        } else {
+            (storage[1 + self.ocr_aggregators().count()], INT256_SIGN_BIT)
+        };
+        if answer.bit(sign_bit) {
+            return Err(PriceError::InvalidState(format!("negative answer from Chainlink feed {}", self.proxy)));
+        }
+        let dec_denom = self.feed().dec_denom;
+        if inverse_it {
+            common::mul_div(&[precision_factor, dec_denom], &[answer])
+        } else {
+            common::mul_div(&[precision_factor, answer], &[dec_denom])
+        }
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        Ok([self.base_token, self.quote_token])
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let current_phase = caller.storage(self.proxy, B256::from(U256::from(self.current_phase_slot))).await?;
+        let phase_id = common::view_call(caller, self.proxy, IAggregatorProxy::phaseIdCall {}).await?;
+        let aggregator = common::view_call(caller, self.proxy, IAggregatorProxy::aggregatorCall {}).await?;
+        common::check_layout(
+            &self.name(),
+            (current_phase & U16_MASK, phase_aggregator(current_phase)),
+            (U256::from(phase_id), aggregator),
+        )
+    }
+}
+
+/// Raw `int192` answer of the transmission read by `dependent_reads`.
+fn transmitted_answer(storage: &[U256]) -> U256 {
+    // Transmission { int192 answer; uint64 timestamp }
+    storage[storage.len() - 1] & U192_MASK
+}
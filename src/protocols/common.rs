@@ -1,5 +1,11 @@
-use alloy::primitives::{B256, U256, Address};
+use std::fmt;
+
+use alloy::primitives::{B256, U256, U512, Address, Bytes, address, keccak256};
+use alloy::primitives::ruint::UintTryFrom;
+use alloy::sol_types::SolCall;
+use alloy::sol;
 use eyre::Result;
+use crate::error::PriceError;
 
 
 #[async_trait::async_trait]
@@ -7,44 +13,230 @@ pub trait Protocol {
 
     fn name(&self) -> String;
 
+    /// Contract the source is read from: the pool, or the feed, vault or token
+    /// contract for sources that are not pools (the `PoolManager` for UniV4).
+    fn pool_address(&self) -> Address;
+
+    /// Loads whatever the protocol needs from the chain before its storage reads
+    /// can be declared, e.g. pool token lists or immutable parameters.
+    async fn prepare(
+        &mut self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     async fn fetch_tokens(
         &self,
-        provider: &alloy::providers::RootProvider,
+        caller: &dyn ViewCaller,
     ) -> Result<[Address; 2]>;
 
-    fn storage_slot(&self) -> B256;
+    /// Checks that the configured storage layout decodes to what the
+    /// contract's own getters return, so a wrong slot fails at build time
+    /// instead of producing garbage prices.
+    async fn verify_layout(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<()> {
+        Ok(())
+    }
 
-    fn storage_target(&self) -> Address;
+    /// Storage reads needed to price the source at a block; their values are
+    /// passed to `retrieve_price_from_storage` in the same order.
+    fn state_reads(&self) -> Vec<StateRead>;
 
+    /// Reads whose location depends on the values of `state_reads`, e.g. a
+    /// mapping keyed by a stored round id. Their values are appended after
+    /// the `state_reads` values.
+    fn dependent_reads(&self, _storage: &[U256]) -> Vec<StateRead> {
+        Vec::new()
+    }
+
+    /// Conditions that hold from the first block the source can be priced at:
+    /// by default the code of every required `state_reads` target. Sources
+    /// living inside a contract that predates them (the Balancer Vault, the
+    /// V4 PoolManager) need their own.
+    fn deployment_probes(&self) -> Vec<DeploymentProbe> {
+        self.state_reads()
+            .iter()
+            .filter(|read| !read.is_optional())
+            .map(|read| DeploymentProbe::Code(read.target()))
+            .collect()
+    }
+
+    /// Degenerate states (empty pools, overflowing prices) are reported as a
+    /// `PriceError` so the row can be recorded instead of failing the run.
     fn retrieve_price_from_storage(
         &self,
-        storage: U256,
+        storage: &[U256],
         inverse_it: bool,
         dec_denoms: [U256; 2],
         precision_factor: U256,
-    ) -> Result<U256>;
+    ) -> Result<U256, PriceError>;
+
+}
+
+/// Executes read-only contract calls against the chain's current state.
+///
+/// Calls and storage reads of one caller all see the same block.
+#[async_trait::async_trait]
+pub trait ViewCaller: Send + Sync {
+
+    async fn call(&self, to: Address, input: Bytes) -> Result<Bytes>;
+
+    /// Raw value of a storage slot, zero if empty.
+    async fn storage(&self, address: Address, slot: B256) -> Result<U256>;
+
+}
 
+pub async fn view_call<C: SolCall>(
+    caller: &dyn ViewCaller,
+    to: Address,
+    call: C,
+) -> Result<C::Return> {
+    let output = caller.call(to, call.abi_encode().into()).await?;
+    Ok(C::abi_decode_returns(&output)?)
+}
+
+/// Piece of state a price is computed from.
+#[derive(Debug, Clone)]
+pub enum StateRead {
+    /// Single storage slot of a contract.
+    Storage {
+        target: Address,
+        slot: B256,
+        /// Empty slots are read as zero instead of failing the block, and
+        /// the target is not required to be deployed yet.
+        optional: bool,
+    },
+    /// View call executed at the block, for values a contract computes
+    /// instead of storing. The first word of the output is the value.
+    Call {
+        target: Address,
+        input: Bytes,
+    },
+}
+
+impl StateRead {
+
+    pub fn storage(target: Address, slot: B256) -> Self {
+        Self::Storage { target, slot, optional: false }
+    }
+
+    pub fn optional_storage(target: Address, slot: B256) -> Self {
+        Self::Storage { target, slot, optional: true }
+    }
+
+    pub fn call<C: SolCall>(target: Address, call: C) -> Self {
+        Self::Call { target, input: call.abi_encode().into() }
+    }
+
+    pub fn is_optional(&self) -> bool {
+        matches!(self, Self::Storage { optional: true, .. })
+    }
+
+    pub fn target(&self) -> Address {
+        match self {
+            Self::Storage { target, .. } | Self::Call { target, .. } => *target,
+        }
+    }
+
+}
+
+/// Condition that starts holding at some block and keeps holding after it,
+/// found by binary search over the history.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeploymentProbe {
+    /// The contract has code.
+    Code(Address),
+    /// The storage slot is non-empty.
+    Storage { target: Address, slot: B256 },
+}
+
+impl fmt::Display for DeploymentProbe {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code(address) => write!(f, "code at {address}"),
+            Self::Storage { target, slot } => write!(f, "value in slot {slot} of {target}"),
+        }
+    }
+
+}
+
+/// Value of a `StateRead::Call` from the raw call output.
+pub fn call_output_word(output: &[u8]) -> Result<U256> {
+    if output.len() < 32 {
+        return Err(eyre::eyre!("Call output of {} bytes is shorter than a word", output.len()));
+    }
+    Ok(U256::from_be_slice(&output[..32]))
+}
+
+/// Location of `mapping[key]` for a mapping declared at `slot`.
+pub fn mapping_slot(key: B256, slot: B256) -> B256 {
+    keccak256([key.as_slice(), slot.as_slice()].concat())
+}
+
+/// Slot of the `offset`-th word of a struct or fixed array starting at `slot`.
+pub fn slot_offset(slot: B256, offset: u64) -> B256 {
+    B256::from(U256::from_be_bytes(slot.0) + U256::from(offset))
+}
+
+/// `floor(prod(numerators) / prod(denominators))` with 512-bit intermediates,
+/// so prices are exact to the last digit of the configured precision.
+pub fn mul_div(numerators: &[U256], denominators: &[U256]) -> Result<U256, PriceError> {
+    let product = |factors: &[U256]| {
+        factors
+            .iter()
+            .try_fold(U512::from(1u8), |acc, factor| acc.checked_mul(U512::from(*factor)))
+            .ok_or(PriceError::Overflow)
+    };
+    let numerator = product(numerators)?;
+    let denominator = product(denominators)?;
+    if denominator.is_zero() {
+        return Err(PriceError::ZeroLiquidity);
+    }
+    U256::uint_try_from(numerator / denominator).map_err(|_| PriceError::Overflow)
+}
+
+/// Values of `reads` at the caller's block, for checks outside the fetch loop.
+pub async fn read_state(caller: &dyn ViewCaller, reads: &[StateRead]) -> Result<Vec<U256>> {
+    let mut values = Vec::with_capacity(reads.len());
+    for read in reads {
+        let value = match read {
+            StateRead::Storage { target, slot, .. } => caller.storage(*target, *slot).await?,
+            StateRead::Call { target, input } => call_output_word(&caller.call(*target, input.clone()).await?)?,
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// Fails `verify_layout` of `source` when a storage-decoded value differs
+/// from the one its getter returned.
+pub fn check_layout<T: PartialEq + std::fmt::Debug>(source: &str, from_storage: T, from_getter: T) -> Result<()> {
+    if from_storage != from_getter {
+        return Err(eyre::eyre!(
+            "Storage layout mismatch for {source}: storage decodes to {from_storage:?}, getter returned {from_getter:?}"
+        ));
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
 pub struct TokenInfo {
-    symbol: String,
-    decimals: u8,
+    pub symbol: String,
+    pub decimals: u8,
     pub dec_denom: U256,
 }
 
 
-use alloy::providers::RootProvider;
-use alloy::sol;
-
 sol!{
-    #[sol(rpc)]
     interface IUniswapPool {
         function token0() external view returns (address);
         function token1() external view returns (address);
     }
 
-    #[sol(rpc)]
     interface IERC20 {
         function symbol() external view returns (string);
         function decimals() external view returns (uint8);
@@ -52,22 +244,74 @@ sol!{
 }
 
 pub async fn uniswap_pool_tokens(
-    provider: &RootProvider,
+    caller: &dyn ViewCaller,
     pool: Address,
 ) -> Result<[Address; 2]> {
-    let pool_contract = IUniswapPool::new(pool, provider);
-    let token0 = pool_contract.token0().call().await?;
-    let token1 = pool_contract.token1().call().await?;
+    let token0 = view_call(caller, pool, IUniswapPool::token0Call {}).await?;
+    let token1 = view_call(caller, pool, IUniswapPool::token1Call {}).await?;
     Ok([token0, token1])
 }
 
+/// Placeholder some protocols (e.g. Curve) use for native ETH.
+const NATIVE_ETH_PLACEHOLDER: Address = address!("EeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE");
+/// Chainlink `Denominations` for assets without an ERC20 contract.
+const CHAINLINK_USD: Address = address!("0000000000000000000000000000000000000348");
+const CHAINLINK_BTC: Address = address!("bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB");
+
 pub async fn fetch_token_info(
-    provider: &RootProvider,
+    caller: &dyn ViewCaller,
     token: Address,
 ) -> Result<TokenInfo> {
-    let token_contract = IERC20::new(token, provider);
-    let symbol = token_contract.symbol().call().await?;
-    let decimals = token_contract.decimals().call().await?;
+    if let Some(info) = synthetic_token_info(token) {
+        return Ok(info);
+    }
+    let symbol = view_call(caller, token, IERC20::symbolCall {}).await?;
+    let decimals = view_call(caller, token, IERC20::decimalsCall {}).await?;
     let dec_denom = U256::from(10u64).pow(U256::from(decimals));
     Ok(TokenInfo { symbol, decimals, dec_denom })
-}
\ No newline at end of file
+}
+
+/// Native ETH and Chainlink denominations have no ERC20 contract to call,
+/// so their info is synthesized.
+fn synthetic_token_info(token: Address) -> Option<TokenInfo> {
+    let (symbol, decimals) = match token {
+        Address::ZERO | NATIVE_ETH_PLACEHOLDER => ("ETH", 18),
+        CHAINLINK_USD => ("USD", 8),
+        CHAINLINK_BTC => ("BTC", 8),
+        _ => return None,
+    };
+    Some(TokenInfo {
+        symbol: symbol.to_string(),
+        decimals,
+        dec_denom: U256::from(10u64).pow(U256::from(decimals)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U256;
+    use crate::error::PriceError;
+    use super::mul_div;
+
+    #[test]
+    fn mul_div_floors_with_wide_intermediates() {
+        assert_eq!(mul_div(&[U256::from(7u8)], &[U256::from(2u8)]), Ok(U256::from(3u8)));
+        assert_eq!(mul_div(&[U256::MAX, U256::MAX], &[U256::MAX]), Ok(U256::MAX));
+        assert_eq!(mul_div(&[U256::MAX, U256::MAX - U256::from(1u8)], &[U256::MAX, U256::MAX]), Ok(U256::ZERO));
+    }
+
+    #[test]
+    fn mul_div_zero_denominator_is_zero_liquidity() {
+        assert_eq!(mul_div(&[U256::from(1u8)], &[U256::ZERO]), Err(PriceError::ZeroLiquidity));
+        assert_eq!(mul_div(&[U256::MAX], &[U256::from(5u8), U256::ZERO]), Err(PriceError::ZeroLiquidity));
+    }
+
+    #[test]
+    fn mul_div_overflow() {
+        // result above 256 bits
+        assert_eq!(mul_div(&[U256::MAX, U256::from(2u8)], &[U256::from(1u8)]), Err(PriceError::Overflow));
+        // intermediate product above 512 bits, even though the result would fit
+        assert_eq!(mul_div(&[U256::MAX, U256::MAX, U256::from(2u8)], &[U256::MAX, U256::MAX]), Err(PriceError::Overflow));
+        assert_eq!(mul_div(&[U256::from(1u8)], &[U256::MAX, U256::MAX, U256::from(2u8)]), Err(PriceError::Overflow));
+    }
+}
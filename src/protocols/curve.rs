@@ -0,0 +1,405 @@
+use alloy::primitives::{Address, B256, U256, keccak256, uint};
+use alloy::sol;
+use eyre::{Result, eyre};
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+
+
+const E18: U256 = uint!(1000000000000000000_U256);
+const D_MAX_ITERATIONS: usize = 255;
+
+sol!{
+    interface ICurvePool {
+        function coins(uint256 i) external view returns (address);
+        function balances(uint256 i) external view returns (uint256);
+        function A() external view returns (uint256);
+        function A_precise() external view returns (uint256);
+    }
+
+    interface ICurveCryptoPool2 {
+        function price_scale() external view returns (uint256);
+        function last_prices() external view returns (uint256);
+    }
+
+    interface ICurveCryptoPoolN {
+        function price_scale(uint256 k) external view returns (uint256);
+        function last_prices(uint256 k) external view returns (uint256);
+    }
+}
+
+/// Curve pool priced as coin `i` in terms of coin `j`.
+///
+/// Storage layouts differ between pool generations, so the slots of the
+/// relevant variables have to be provided. Pools compiled with Vyper < 0.3
+/// keep arrays at `keccak256(slot) + index` and need `hashed_arrays = true`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Curve {
+    pool: Address,
+    i: usize,
+    j: usize,
+    #[serde(default)]
+    hashed_arrays: bool,
+    #[serde(flatten)]
+    pool_type: CurvePoolType,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "lowercase")]
+pub enum CurvePoolType {
+    /// Reads `balances` and the amplification coefficient, with a call since
+    /// it is interpolated while A is ramped: `A()` for pools without
+    /// `A_PRECISION`, `A_precise()` when `a_precision` is set. `coin_decimals`
+    /// lists the decimals of every pool coin since all of them enter the invariant.
+    StableSwap {
+        coin_decimals: Vec<u8>,
+        balances_slot: u64,
+        #[serde(default = "default_a_precision")]
+        a_precision: u64,
+    },
+    /// Reads the `n_coins - 1` prices (`last_prices` or `price_scale`) which
+    /// are quoted against coin 0 with 18 decimals.
+    CryptoSwap {
+        n_coins: usize,
+        price_slot: u64,
+    },
+}
+
+fn default_a_precision() -> u64 {
+    1
+}
+
+impl Curve {
+
+    fn n_coins(&self) -> usize {
+        match &self.pool_type {
+            CurvePoolType::StableSwap { coin_decimals, .. } => coin_decimals.len(),
+            CurvePoolType::CryptoSwap { n_coins, .. } => *n_coins,
+        }
+    }
+
+    /// Rejects configurations that would index out of the pool's coins or
+    /// can't be normalized to 18 decimals.
+    fn validate(&self) -> Result<()> {
+        let n_coins = self.n_coins();
+        if n_coins < 2 {
+            return Err(eyre!("{} must have at least 2 coins, got {n_coins}", self.name()));
+        }
+        if self.i >= n_coins || self.j >= n_coins || self.i == self.j {
+            return Err(eyre!(
+                "{}: i = {} and j = {} must be distinct coin indices below {n_coins}",
+                self.name(), self.i, self.j,
+            ));
+        }
+        if let CurvePoolType::StableSwap { coin_decimals, a_precision, .. } = &self.pool_type {
+            if let Some(decimals) = coin_decimals.iter().find(|&&decimals| decimals > 18) {
+                return Err(eyre!("{}: coins with {decimals} decimals are not supported", self.name()));
+            }
+            if *a_precision == 0 {
+                return Err(eyre!("{}: a_precision must be positive", self.name()));
+            }
+        }
+        Ok(())
+    }
+
+    fn array_slot(&self, base_slot: u64, index: usize) -> B256 {
+        let base = U256::from(base_slot);
+        let slot = if self.hashed_arrays {
+            U256::from_be_bytes(keccak256(B256::from(base)).0) + U256::from(index)
+        } else {
+            base + U256::from(index)
+        };
+        B256::from(slot)
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Protocol for Curve {
+    fn name(&self) -> String {
+        format!("Curve: {}", self.pool)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.pool
+    }
+
+    async fn prepare(&mut self, _caller: &dyn ViewCaller) -> Result<()> {
+        self.validate()
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        match &self.pool_type {
+            CurvePoolType::StableSwap { coin_decimals, balances_slot, a_precision } => {
+                let mut reads = (0..coin_decimals.len())
+                    .map(|k| StateRead::storage(self.pool, self.array_slot(*balances_slot, k)))
+                    .collect::<Vec<_>>();
+                reads.push(if *a_precision == 1 {
+                    StateRead::call(self.pool, ICurvePool::ACall {})
+                } else {
+                    StateRead::call(self.pool, ICurvePool::A_preciseCall {})
+                });
+                reads
+            }
+            CurvePoolType::CryptoSwap { n_coins, price_slot } => {
+                (0..n_coins - 1)
+                    .map(|k| StateRead::storage(self.pool, self.array_slot(*price_slot, k)))
+                    .collect()
+            }
+        }
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        _dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let (i, j) = if inverse_it { (self.j, self.i) } else { (self.i, self.j) };
+        match &self.pool_type {
+            CurvePoolType::StableSwap { coin_decimals, a_precision, .. } => {
+                let (balances, amp) = storage.split_at(coin_decimals.len());
+                let xp = balances
+                    .iter()
+                    .zip(coin_decimals)
+                    .map(|(balance, &decimals)| {
+                        balance
+                            .checked_mul(U256::from(10u64).pow(U256::from(18 - decimals)))
+                            .ok_or(PriceError::Overflow)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                stableswap_price(&xp, amp[0], U256::from(*a_precision), i, j, precision_factor)
+            }
+            CurvePoolType::CryptoSwap { .. } => {
+                let price_of = |k: usize| if k == 0 { E18 } else { storage[k - 1] };
+                common::mul_div(&[precision_factor, price_of(i)], &[price_of(j)])
+            }
+        }
+    }
+
+    async fn fetch_tokens(
+        &self,
+        caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        let coin_i = common::view_call(caller, self.pool, ICurvePool::coinsCall { i: U256::from(self.i) }).await?;
+        let coin_j = common::view_call(caller, self.pool, ICurvePool::coinsCall { i: U256::from(self.j) }).await?;
+        Ok([coin_i, coin_j])
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        match &self.pool_type {
+            CurvePoolType::StableSwap { coin_decimals, balances_slot, .. } => {
+                for k in 0..coin_decimals.len() {
+                    let from_storage = caller.storage(self.pool, self.array_slot(*balances_slot, k)).await?;
+                    let from_getter = common::view_call(caller, self.pool, ICurvePool::balancesCall { i: U256::from(k) }).await?;
+                    common::check_layout(&self.name(), from_storage, from_getter)?;
+                }
+                Ok(())
+            }
+            CurvePoolType::CryptoSwap { n_coins, price_slot } => {
+                let mut from_storage = Vec::with_capacity(n_coins - 1);
+                for k in 0..n_coins - 1 {
+                    from_storage.push(caller.storage(self.pool, self.array_slot(*price_slot, k)).await?);
+                }
+                let (price_scale, last_prices) = crypto_pool_prices(caller, self.pool, *n_coins).await?;
+                // `price_slot` may point at either array
+                if from_storage == last_prices {
+                    return Ok(());
+                }
+                common::check_layout(&self.name(), from_storage, price_scale)
+            }
+        }
+    }
+}
+
+/// `price_scale` and `last_prices` of a CryptoSwap pool from its getters,
+/// which take no index in 2-coin pools.
+async fn crypto_pool_prices(
+    caller: &dyn ViewCaller,
+    pool: Address,
+    n_coins: usize,
+) -> Result<(Vec<U256>, Vec<U256>)> {
+    if n_coins == 2 {
+        let price_scale = common::view_call(caller, pool, ICurveCryptoPool2::price_scaleCall {}).await?;
+        let last_prices = common::view_call(caller, pool, ICurveCryptoPool2::last_pricesCall {}).await?;
+        return Ok((vec![price_scale], vec![last_prices]));
+    }
+    let (mut price_scale, mut last_prices) = (Vec::new(), Vec::new());
+    for k in 0..n_coins - 1 {
+        let k = U256::from(k);
+        price_scale.push(common::view_call(caller, pool, ICurveCryptoPoolN::price_scaleCall { k }).await?);
+        last_prices.push(common::view_call(caller, pool, ICurveCryptoPoolN::last_pricesCall { k }).await?);
+    }
+    Ok((price_scale, last_prices))
+}
+
+/// Marginal price of coin `i` in coin `j` on the StableSwap invariant, given
+/// balances normalized to 18 decimals.
+///
+/// With `Ann = A * n` and `D_P = D^(n+1) / (n^n * prod(x))` the invariant's
+/// partial derivatives give `x_j * (Ann * x_i + D_P) / (x_i * (Ann * x_j + D_P))`.
+fn stableswap_price(
+    xp: &[U256],
+    amp: U256,
+    a_precision: U256,
+    i: usize,
+    j: usize,
+    precision_factor: U256,
+) -> Result<U256, PriceError> {
+    if xp.iter().any(|x| x.is_zero()) {
+        return Err(PriceError::ZeroLiquidity);
+    }
+    let n = U256::from(xp.len());
+    let ann = checked_mul(amp, n)?;
+    let d = stableswap_d(xp, ann, a_precision)?;
+    let d_p = stableswap_d_p(xp, d)?;
+
+    common::mul_div(
+        &[precision_factor, xp[j], checked_add(checked_mul(ann, xp[i])?, checked_mul(a_precision, d_p)?)?],
+        &[xp[i], checked_add(checked_mul(ann, xp[j])?, checked_mul(a_precision, d_p)?)?],
+    )
+}
+
+/// Newton iteration for the invariant `D`, mirroring Curve's `get_D`.
+fn stableswap_d(xp: &[U256], ann: U256, a_precision: U256) -> Result<U256, PriceError> {
+    let n = U256::from(xp.len());
+    let sum = xp.iter().try_fold(U256::ZERO, |acc, x| checked_add(acc, *x))?;
+    if sum.is_zero() {
+        return Ok(U256::ZERO);
+    }
+
+    let mut d = sum;
+    for _ in 0..D_MAX_ITERATIONS {
+        let d_p = stableswap_d_p(xp, d)?;
+        let d_prev = d;
+        let numerator = checked_mul(checked_add(checked_mul(ann, sum)? / a_precision, checked_mul(d_p, n)?)?, d)?;
+        let denominator = checked_add(
+            checked_mul(checked_sub(ann, a_precision)?, d)? / a_precision,
+            checked_mul(n + U256::from(1), d_p)?,
+        )?;
+        d = numerator
+            .checked_div(denominator)
+            .ok_or_else(|| PriceError::InvalidState("StableSwap invariant is degenerate".to_string()))?;
+        if d.abs_diff(d_prev) <= U256::from(1) {
+            return Ok(d);
+        }
+    }
+    Err(PriceError::InvalidState("StableSwap invariant did not converge".to_string()))
+}
+
+/// `D^(n+1) / (n^n * prod(x))`, divided down at every step like Curve does.
+fn stableswap_d_p(xp: &[U256], d: U256) -> Result<U256, PriceError> {
+    let n = U256::from(xp.len());
+    xp.iter().try_fold(d, |d_p, x| Ok(checked_mul(d_p, d)? / checked_mul(*x, n)?))
+}
+
+fn checked_add(a: U256, b: U256) -> Result<U256, PriceError> {
+    a.checked_add(b).ok_or(PriceError::Overflow)
+}
+
+fn checked_sub(a: U256, b: U256) -> Result<U256, PriceError> {
+    a.checked_sub(b).ok_or(PriceError::Overflow)
+}
+
+fn checked_mul(a: U256, b: U256) -> Result<U256, PriceError> {
+    a.checked_mul(b).ok_or(PriceError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{U256, uint};
+    use crate::error::PriceError;
+    use super::{stableswap_d, stableswap_price};
+
+    const E18: U256 = uint!(1000000000000000000_U256);
+
+    fn pow10(exp: u64) -> U256 {
+        U256::from(10u64).pow(U256::from(exp))
+    }
+
+    /// Output of a fee-less swap of `dx` from coin `i` to coin `j`, in
+    /// normalized balances, following `get_y` of the 3pool contract (with
+    /// `A_PRECISION` as in later StableSwap pools).
+    fn get_dy(xp: &[U256], amp: U256, a_precision: U256, i: usize, j: usize, dx: U256) -> U256 {
+        let n = U256::from(xp.len());
+        let ann = amp * n;
+        let d = stableswap_d(xp, ann, a_precision).unwrap();
+        let (mut c, mut s) = (d, U256::ZERO);
+        for (k, &x) in xp.iter().enumerate() {
+            let x = match k {
+                _ if k == i => x + dx,
+                _ if k == j => continue,
+                _ => x,
+            };
+            s += x;
+            c = c * d / (x * n);
+        }
+        c = c * d * a_precision / (ann * n);
+        let b = s + d * a_precision / ann;
+        let mut y = d;
+        for _ in 0..255 {
+            let y_prev = y;
+            y = (y * y + c) / (U256::from(2) * y + b - d);
+            if y.abs_diff(y_prev) <= U256::from(1) {
+                break;
+            }
+        }
+        xp[j] - y - U256::from(1)
+    }
+
+    #[test]
+    fn balanced_pool_prices_at_one() {
+        for n_coins in [2, 3, 4] {
+            let xp = vec![pow10(8) * E18; n_coins];
+            for (amp, a_precision) in [(1, 1), (2000, 1), (200000, 100)] {
+                let price = stableswap_price(&xp, U256::from(amp), U256::from(a_precision), 0, n_coins - 1, pow10(15));
+                assert_eq!(price, Ok(pow10(15)), "{n_coins} coins, A {amp}, A_PRECISION {a_precision}");
+            }
+        }
+    }
+
+    /// The marginal price of an imbalanced 3pool-like state matches the rate
+    /// of a swap small enough for its slippage to stay below the tolerance.
+    #[test]
+    fn imbalanced_pool_matches_get_dy() {
+        let xp = [U256::from(120_000_000u64) * E18, U256::from(210_000_000u64) * E18, U256::from(80_000_000u64) * E18];
+        for (amp, a_precision) in [(2000, 1), (200000, 100), (10, 1)] {
+            let (amp, a_precision) = (U256::from(amp), U256::from(a_precision));
+            for (i, j) in [(0, 1), (1, 0), (2, 1), (0, 2)] {
+                let price = stableswap_price(&xp, amp, a_precision, i, j, E18).unwrap();
+                let swap_rate = get_dy(&xp, amp, a_precision, i, j, E18);
+                assert!(
+                    price.abs_diff(swap_rate) <= price / pow10(6),
+                    "coins {i}->{j}, A {amp}: marginal price {price}, swap rate {swap_rate}",
+                );
+                // more of coin i than j makes i cheaper
+                assert_eq!(price < E18, xp[i] > xp[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn dust_balances_do_not_converge() {
+        let xp = [U256::from(33u8), U256::from(1u8)];
+        assert_eq!(
+            stableswap_price(&xp, U256::from(1u8), U256::from(1u8), 0, 1, E18),
+            Err(PriceError::InvalidState("StableSwap invariant did not converge".to_string())),
+        );
+    }
+
+    #[test]
+    fn overflowing_state_is_an_error() {
+        let xp = [U256::from(1u8) << 200, U256::from(1u8) << 200];
+        assert_eq!(stableswap_price(&xp, U256::from(100u8), U256::from(1u8), 0, 1, E18), Err(PriceError::Overflow));
+        // A below A_PRECISION, as if `A()` were read for a pool needing `A_precise()`
+        let xp = [E18, E18];
+        assert_eq!(stableswap_price(&xp, U256::from(20u8), U256::from(100u8), 0, 1, E18), Err(PriceError::Overflow));
+    }
+
+    #[test]
+    fn empty_coin_is_zero_liquidity() {
+        let xp = [E18, U256::ZERO];
+        assert_eq!(stableswap_price(&xp, U256::from(100u8), U256::from(1u8), 0, 1, E18), Err(PriceError::ZeroLiquidity));
+    }
+}
@@ -0,0 +1,82 @@
+use alloy::primitives::{Address, B256, U256};
+use alloy::sol;
+use eyre::Result;
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+
+
+sol!{
+    interface IERC4626 {
+        function asset() external view returns (address);
+        function totalAssets() external view returns (uint256);
+        function totalSupply() external view returns (uint256);
+    }
+}
+
+/// ERC-4626 vault share priced in its underlying `asset()`, as
+/// `totalAssets / totalSupply`.
+///
+/// Most vaults compute `totalAssets` (accrued interest, vesting rewards,
+/// positions in other protocols), so by default both values are read with a
+/// call at every block. Vaults that store them can set the slots instead.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Erc4626 {
+    vault: Address,
+    total_assets_slot: Option<u64>,
+    total_supply_slot: Option<u64>,
+}
+
+#[async_trait::async_trait]
+impl Protocol for Erc4626 {
+    fn name(&self) -> String {
+        format!("ERC4626: {}", self.vault)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.vault
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        let read = |slot: Option<u64>, call: StateRead| match slot {
+            Some(slot) => StateRead::storage(self.vault, B256::from(U256::from(slot))),
+            None => call,
+        };
+        vec![
+            read(self.total_assets_slot, StateRead::call(self.vault, IERC4626::totalAssetsCall {})),
+            read(self.total_supply_slot, StateRead::call(self.vault, IERC4626::totalSupplyCall {})),
+        ]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let (total_assets, total_supply) = (storage[0], storage[1]);
+        if total_supply.is_zero() || total_assets.is_zero() {
+            return Err(PriceError::ZeroLiquidity);
+        }
+        if inverse_it {
+            common::mul_div(&[precision_factor, total_supply, dec_denoms[1]], &[total_assets, dec_denoms[0]])
+        } else {
+            common::mul_div(&[precision_factor, total_assets, dec_denoms[0]], &[total_supply, dec_denoms[1]])
+        }
+    }
+
+    async fn fetch_tokens(
+        &self,
+        caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        let asset = common::view_call(caller, self.vault, IERC4626::assetCall {}).await?;
+        Ok([self.vault, asset])
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let storage = common::read_state(caller, &self.state_reads()).await?;
+        let total_assets = common::view_call(caller, self.vault, IERC4626::totalAssetsCall {}).await?;
+        let total_supply = common::view_call(caller, self.vault, IERC4626::totalSupplyCall {}).await?;
+        common::check_layout(&self.name(), (storage[0], storage[1]), (total_assets, total_supply))
+    }
+}
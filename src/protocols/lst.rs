@@ -0,0 +1,238 @@
+use alloy::primitives::{Address, B256, U256, address, b256, uint};
+use alloy::sol;
+use eyre::Result;
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+
+
+const E18: U256 = uint!(1000000000000000000_U256);
+const DEPOSIT_SIZE: U256 = uint!(32000000000000000000_U256);
+
+const WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+const WSTETH: Address = address!("7f39C581F595B53c5cb19bD0b3f8dA6c935E2Ca0");
+const STETH: Address = address!("ae7ab96520DE3A18E5e111B5EaAb095312D7fE84");
+const RETH: Address = address!("ae78736Cd615f374D3085123A210448E74Fc6393");
+const CBETH: Address = address!("Be9895146f7AF43049ca1c1AE358B0541Ea49704");
+
+// keccak256("lido.StETH.totalShares")
+const TOTAL_SHARES_POSITION: B256 = b256!("e3b4b636e601189b5f4c6742edf2538ac12bb61ed03e6da26949d69838fa447e");
+// keccak256("lido.Lido.bufferedEther")
+const BUFFERED_ETHER_POSITION: B256 = b256!("ed310af23f61f96daefbcd140b306c0bdbf8c178398299741687b90e794772b0");
+// keccak256("lido.Lido.beaconBalance")
+const CL_BALANCE_POSITION: B256 = b256!("a66d35f054e68143c18f32c990ed5cb972bb68a68f500cd2dd3a16bbf3686483");
+// keccak256("lido.Lido.depositedValidators")
+const DEPOSITED_VALIDATORS_POSITION: B256 = b256!("e6e35175eb53fc006520a2a9c3e9711a7c00de6ff2c32dd31df8c5a24cac1b5c");
+// keccak256("lido.Lido.beaconValidators")
+const CL_VALIDATORS_POSITION: B256 = b256!("9f70001d82b6ef54e9d3725b46581c3eb9ee3aa02b941b6aa54d678a9ca35b10");
+
+sol!{
+    interface IStETH {
+        function getTotalPooledEther() external view returns (uint256);
+        function getTotalShares() external view returns (uint256);
+    }
+
+    interface IRETH {
+        function getExchangeRate() external view returns (uint256);
+    }
+
+    interface ICbETH {
+        function exchangeRate() external view returns (uint256);
+    }
+}
+
+fn default_weth() -> Address {
+    WETH
+}
+
+fn default_wsteth() -> Address {
+    WSTETH
+}
+
+fn default_steth() -> Address {
+    STETH
+}
+
+fn default_reth() -> Address {
+    RETH
+}
+
+fn default_cbeth() -> Address {
+    CBETH
+}
+
+/// Lido wstETH priced at `stEthPerToken`, i.e. total pooled ether over total
+/// shares, both taken from stETH's unstructured storage.
+///
+/// Addresses default to mainnet.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct WstEth {
+    #[serde(default = "default_wsteth")]
+    wsteth: Address,
+    #[serde(default = "default_steth")]
+    steth: Address,
+    #[serde(default = "default_weth")]
+    weth: Address,
+}
+
+impl WstEth {
+
+    /// Total pooled ether and total shares from the reads of `state_reads`.
+    fn pooled_ether_and_shares(storage: &[U256]) -> (U256, U256) {
+        let [total_shares, buffered_ether, cl_balance, deposited_validators, cl_validators] = storage else {
+            unreachable!("stETH reads are fixed")
+        };
+        // validators deposited to but not yet seen on the beacon chain
+        let transient_ether = deposited_validators.saturating_sub(*cl_validators) * DEPOSIT_SIZE;
+        (buffered_ether + cl_balance + transient_ether, *total_shares)
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Protocol for WstEth {
+    fn name(&self) -> String {
+        format!("wstETH: {}", self.wsteth)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.wsteth
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![
+            StateRead::storage(self.steth, TOTAL_SHARES_POSITION),
+            StateRead::optional_storage(self.steth, BUFFERED_ETHER_POSITION),
+            StateRead::optional_storage(self.steth, CL_BALANCE_POSITION),
+            StateRead::optional_storage(self.steth, DEPOSITED_VALIDATORS_POSITION),
+            StateRead::optional_storage(self.steth, CL_VALIDATORS_POSITION),
+        ]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        _dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let (pooled_ether, total_shares) = Self::pooled_ether_and_shares(storage);
+        if pooled_ether.is_zero() {
+            return Err(PriceError::ZeroLiquidity);
+        }
+        if inverse_it {
+            common::mul_div(&[precision_factor, total_shares], &[pooled_ether])
+        } else {
+            common::mul_div(&[precision_factor, pooled_ether], &[total_shares])
+        }
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        Ok([self.wsteth, self.weth])
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let storage = common::read_state(caller, &self.state_reads()).await?;
+        let pooled_ether = common::view_call(caller, self.steth, IStETH::getTotalPooledEtherCall {}).await?;
+        let total_shares = common::view_call(caller, self.steth, IStETH::getTotalSharesCall {}).await?;
+        common::check_layout(&self.name(), Self::pooled_ether_and_shares(&storage), (pooled_ether, total_shares))
+    }
+}
+
+/// Rocket Pool rETH priced at `getExchangeRate()`, which the token derives
+/// from RocketStorage balances, so it is read with a call at every block.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct REth {
+    #[serde(default = "default_reth")]
+    reth: Address,
+    #[serde(default = "default_weth")]
+    weth: Address,
+}
+
+#[async_trait::async_trait]
+impl Protocol for REth {
+    fn name(&self) -> String {
+        format!("rETH: {}", self.reth)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.reth
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::call(self.reth, IRETH::getExchangeRateCall {})]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        _dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        exchange_rate_to_price(storage[0], inverse_it, precision_factor)
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        Ok([self.reth, self.weth])
+    }
+}
+
+/// Coinbase cbETH priced at the oracle-reported `exchangeRate()`, read with a
+/// call at every block.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CbEth {
+    #[serde(default = "default_cbeth")]
+    cbeth: Address,
+    #[serde(default = "default_weth")]
+    weth: Address,
+}
+
+#[async_trait::async_trait]
+impl Protocol for CbEth {
+    fn name(&self) -> String {
+        format!("cbETH: {}", self.cbeth)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.cbeth
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::call(self.cbeth, ICbETH::exchangeRateCall {})]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        _dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        exchange_rate_to_price(storage[0], inverse_it, precision_factor)
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        Ok([self.cbeth, self.weth])
+    }
+}
+
+/// ETH per LST with 18 decimals, as both tokens have 18 decimals.
+fn exchange_rate_to_price(rate: U256, inverse_it: bool, precision_factor: U256) -> Result<U256, PriceError> {
+    if rate.is_zero() {
+        return Err(PriceError::InvalidState("zero LST exchange rate".to_string()));
+    }
+    if inverse_it {
+        common::mul_div(&[precision_factor, E18], &[rate])
+    } else {
+        common::mul_div(&[precision_factor, rate], &[E18])
+    }
+}
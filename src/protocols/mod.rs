@@ -1,9 +1,25 @@
+mod algebra;
+mod balancerv2;
+mod chainlink;
 mod common;
+mod curve;
+mod erc4626;
+mod lst;
+mod solidly;
 mod univ2;
 mod univ3;
+mod univ4;
 
-pub use common::fetch_token_info;
-pub use common::{Protocol, TokenInfo};
+pub use common::{call_output_word, fetch_token_info};
+pub use common::{DeploymentProbe, Protocol, StateRead, TokenInfo, ViewCaller};
+pub use algebra::Algebra;
+pub use balancerv2::BalancerV2;
+pub use chainlink::Chainlink;
+pub use curve::Curve;
+pub use erc4626::Erc4626;
+pub use lst::{CbEth, REth, WstEth};
+pub use solidly::Solidly;
 pub use univ2::UniV2;
 pub use univ3::UniV3;
+pub use univ4::UniV4;
 pub type BoxedProtocol = Box<dyn Protocol + Send + Sync>;
\ No newline at end of file
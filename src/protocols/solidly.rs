@@ -0,0 +1,108 @@
+use alloy::primitives::{Address, B256, U256, U512, uint};
+use alloy::primitives::ruint::UintTryFrom;
+use alloy::sol;
+use eyre::Result;
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
+
+
+const E18: U256 = uint!(1000000000000000000_U256);
+
+sol!{
+    interface ISolidlyPool {
+        function getReserves() external view returns (uint256 reserve0, uint256 reserve1, uint256 blockTimestampLast);
+    }
+}
+
+/// Solidly-style pair (Velodrome V2, Aerodrome and their forks).
+///
+/// Volatile pairs are priced like UniV2; stable pairs use the marginal price
+/// of the `x^3y + y^3x` invariant. Reserves are full `uint256` slots, which
+/// default to the Velodrome V2 layout and can be overridden for other forks.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Solidly {
+    pool: Address,
+    stable: bool,
+    #[serde(default = "default_reserve0_slot")]
+    reserve0_slot: u64,
+    #[serde(default = "default_reserve1_slot")]
+    reserve1_slot: u64,
+}
+
+fn default_reserve0_slot() -> u64 {
+    18
+}
+
+fn default_reserve1_slot() -> u64 {
+    19
+}
+
+#[async_trait::async_trait]
+impl Protocol for Solidly {
+
+    fn name(&self) -> String {
+        format!("Solidly: {}", self.pool)
+    }
+
+    fn pool_address(&self) -> Address {
+        self.pool
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![
+            StateRead::storage(self.pool, B256::from(U256::from(self.reserve0_slot))),
+            StateRead::storage(self.pool, B256::from(U256::from(self.reserve1_slot))),
+        ]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let (reserve0, reserve1) = (storage[0], storage[1]);
+        if !self.stable {
+            return if inverse_it {
+                common::mul_div(&[precision_factor, reserve0, dec_denoms[1]], &[reserve1, dec_denoms[0]])
+            } else {
+                common::mul_div(&[precision_factor, reserve1, dec_denoms[0]], &[reserve0, dec_denoms[1]])
+            };
+        }
+
+        // dy/dx of x^3y + y^3x is (3x^2y + y^3) / (x^3 + 3xy^2), on reserves scaled to 18 decimals
+        let x = U512::from(common::mul_div(&[reserve0, E18], &[dec_denoms[0]])?);
+        let y = U512::from(common::mul_div(&[reserve1, E18], &[dec_denoms[1]])?);
+        let three = U512::from(3u8);
+        // a * (a^2 + 3b^2)
+        let cubic = |a: U512, b: U512| {
+            a.checked_mul(a.checked_mul(a)?.checked_add(three.checked_mul(b.checked_mul(b)?)?)?)
+                .ok_or(PriceError::Overflow)
+        };
+        let (numerator, denominator) = (cubic(y, x)?, cubic(x, y)?);
+        let (numerator, denominator) =
+            if inverse_it { (denominator, numerator) } else { (numerator, denominator) };
+        if denominator.is_zero() {
+            return Err(PriceError::ZeroLiquidity);
+        }
+        let price = U512::from(precision_factor)
+            .checked_mul(numerator)
+            .ok_or(PriceError::Overflow)?
+            / denominator;
+        U256::uint_try_from(price).map_err(|_| PriceError::Overflow)
+    }
+
+    async fn fetch_tokens(
+        &self,
+        caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        common::uniswap_pool_tokens(caller, self.pool).await
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let storage = common::read_state(caller, &self.state_reads()).await?;
+        let reserves = common::view_call(caller, self.pool, ISolidlyPool::getReservesCall {}).await?;
+        common::check_layout(&self.name(), (storage[0], storage[1]), (reserves.reserve0, reserves.reserve1))
+    }
+}
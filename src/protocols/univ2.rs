@@ -1,14 +1,50 @@
-use alloy::primitives::{Address, B256, U256, uint, b256};
+use alloy::primitives::{Address, B256, U256, uint};
+use alloy::sol;
 use eyre::Result;
-use super::common::{self, Protocol};
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
 
 
-const U112_MASK: U256 = uint!(5192296858534827628530496329220095_U256);
-const UNIV2_RESERVES_SLOT: B256 = b256!("0000000000000000000000000000000000000000000000000000000000000008");
+pub(super) const U112_MASK: U256 = uint!(5192296858534827628530496329220095_U256);
 
+sol!{
+    interface IUniswapV2Pair {
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+    }
+}
+
+/// UniV2 pair or fork. Reserves are two `uint112` packed in `reserves_slot`,
+/// at bit offsets `reserve0_offset` and `reserve1_offset`; forks with extra
+/// inherited state variables need these overridden.
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct UniV2 {
     pool: Address,
+    #[serde(default = "default_reserves_slot")]
+    reserves_slot: u64,
+    #[serde(default)]
+    reserve0_offset: usize,
+    #[serde(default = "default_reserve1_offset")]
+    reserve1_offset: usize,
+}
+
+fn default_reserves_slot() -> u64 {
+    8
+}
+
+fn default_reserve1_offset() -> usize {
+    112
+}
+
+impl UniV2 {
+
+    fn reserves(&self, reserves_word: U256) -> (U256, U256) {
+        (reserves_word >> self.reserve0_offset & U112_MASK, reserves_word >> self.reserve1_offset & U112_MASK)
+    }
+
+    fn reserves_slot(&self) -> B256 {
+        B256::from(U256::from(self.reserves_slot))
+    }
+
 }
 
 #[async_trait::async_trait]
@@ -18,37 +54,43 @@ impl Protocol for UniV2 {
         format!("UniV2: {}", self.pool)
     }
 
-    fn storage_slot(&self) -> B256 {
-        UNIV2_RESERVES_SLOT
+    fn pool_address(&self) -> Address {
+        self.pool
     }
 
-    fn storage_target(&self) -> Address {
-        self.pool
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::storage(self.pool, self.reserves_slot())]
     }
 
     fn retrieve_price_from_storage(
         &self,
-        storage: U256,
+        storage: &[U256],
         inverse_it: bool,
         dec_denoms: [U256; 2],
         precision_factor: U256,
-    ) -> Result<U256> {
-        let token0_reserve = storage & U112_MASK;
-        let token1_reserve = storage >> 112 & U112_MASK;
-        let price = 
-            if inverse_it {
-                precision_factor * token0_reserve * dec_denoms[1] / (token1_reserve * dec_denoms[0])
-            } else {
-                precision_factor * token1_reserve * dec_denoms[0] / (token0_reserve * dec_denoms[1])
-            };
-
-        Ok(price)
+    ) -> Result<U256, PriceError> {
+        let (token0_reserve, token1_reserve) = self.reserves(storage[0]);
+        if inverse_it {
+            common::mul_div(&[precision_factor, token0_reserve, dec_denoms[1]], &[token1_reserve, dec_denoms[0]])
+        } else {
+            common::mul_div(&[precision_factor, token1_reserve, dec_denoms[0]], &[token0_reserve, dec_denoms[1]])
+        }
     }
 
     async fn fetch_tokens(
         &self,
-        provider: &alloy::providers::RootProvider,
+        caller: &dyn ViewCaller,
     ) -> Result<[Address; 2]> {
-        common::uniswap_pool_tokens(provider, self.pool).await
+        common::uniswap_pool_tokens(caller, self.pool).await
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let reserves_word = caller.storage(self.pool, self.reserves_slot()).await?;
+        let reserves = common::view_call(caller, self.pool, IUniswapV2Pair::getReservesCall {}).await?;
+        common::check_layout(
+            &self.name(),
+            self.reserves(reserves_word),
+            (U256::from(reserves.reserve0), U256::from(reserves.reserve1)),
+        )
     }
 }
@@ -1,17 +1,42 @@
 use alloy::primitives::{Address, B256, U256, uint};
+use alloy::sol;
 use eyre::Result;
-use super::common::{self, Protocol};
+use crate::error::PriceError;
+use super::common::{self, Protocol, StateRead, ViewCaller};
 
 
 const TWO_POW_96: U256 = uint!(79228162514264337593543950336_U256);
-const U160_MASK: U256 = uint!(1461501637330902918203684832716283019655932542975_U256);
-const UNIV3_SQRT_PRICE_X96_SLOT: B256 = B256::ZERO;
-const E30: U256 = uint!(1000000000000000000000000000000_U256);
-const E15: U256 = uint!(1000000000000000_U256);
+pub(super) const U160_MASK: U256 = uint!(1461501637330902918203684832716283019655932542975_U256);
 
+sol!{
+    interface IUniswapV3Pool {
+        // only the leading field, which forks agree on
+        function slot0() external view returns (uint160 sqrtPriceX96);
+    }
+}
+
+/// UniV3 pool or fork. `sqrtPriceX96` is the `uint160` at bit
+/// `sqrt_price_offset` of `sqrt_price_slot`, i.e. the start of `slot0` for
+/// pools without extra inherited state variables.
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct UniV3 {
     pool: Address,
+    #[serde(default)]
+    sqrt_price_slot: u64,
+    #[serde(default)]
+    sqrt_price_offset: usize,
+}
+
+impl UniV3 {
+
+    fn sqrt_price_slot(&self) -> B256 {
+        B256::from(U256::from(self.sqrt_price_slot))
+    }
+
+    fn sqrt_price_x96(&self, slot0_word: U256) -> U256 {
+        slot0_word >> self.sqrt_price_offset & U160_MASK
+    }
+
 }
 
 #[async_trait::async_trait]
@@ -20,36 +45,168 @@ impl Protocol for UniV3 {
         format!("UniV3: {}", self.pool)
     }
 
-    fn storage_slot(&self) -> B256 {
-        UNIV3_SQRT_PRICE_X96_SLOT
+    fn pool_address(&self) -> Address {
+        self.pool
     }
 
-    fn storage_target(&self) -> Address {
-        self.pool
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::storage(self.pool, self.sqrt_price_slot())]
     }
 
     fn retrieve_price_from_storage(
         &self,
-        storage: U256,
+        storage: &[U256],
         inverse_it: bool,
         dec_denoms: [U256; 2],
         precision_factor: U256,
-    ) -> Result<U256> {
-        let sqrt_price_x96 = storage & U160_MASK;
-        let price = ((E15 * sqrt_price_x96 / TWO_POW_96) as U256).pow(U256::from(2));
-        
-        let price = if inverse_it {
-            precision_factor * E30 * dec_denoms[1] / (price * dec_denoms[0])
-        } else {
-            precision_factor * price * dec_denoms[0] / dec_denoms[1] / E30
-        };
-        Ok(price)
+    ) -> Result<U256, PriceError> {
+        let sqrt_price_x96 = self.sqrt_price_x96(storage[0]);
+        sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, precision_factor)
     }
 
     async fn fetch_tokens(
         &self,
-        provider: &alloy::providers::RootProvider,
+        caller: &dyn ViewCaller,
     ) -> Result<[Address; 2]> {
-        common::uniswap_pool_tokens(provider, self.pool).await
+        common::uniswap_pool_tokens(caller, self.pool).await
+    }
+
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let slot0_word = caller.storage(self.pool, self.sqrt_price_slot()).await?;
+        let sqrt_price_x96 = common::view_call(caller, self.pool, IUniswapV3Pool::slot0Call {}).await?;
+        common::check_layout(&self.name(), self.sqrt_price_x96(slot0_word), U256::from(sqrt_price_x96))
     }
-}
\ No newline at end of file
+}
+
+/// Price of token0 in token1 from a Q64.96 square root price, as
+/// `sqrtPriceX96^2 / 2^192` scaled by decimals and `precision_factor`.
+pub(super) fn sqrt_price_x96_to_price(
+    sqrt_price_x96: U256,
+    inverse_it: bool,
+    dec_denoms: [U256; 2],
+    precision_factor: U256,
+) -> Result<U256, PriceError> {
+    if inverse_it {
+        common::mul_div(
+            &[precision_factor, TWO_POW_96, TWO_POW_96, dec_denoms[1]],
+            &[sqrt_price_x96, sqrt_price_x96, dec_denoms[0]],
+        )
+    } else {
+        common::mul_div(
+            &[precision_factor, sqrt_price_x96, sqrt_price_x96, dec_denoms[0]],
+            &[TWO_POW_96, TWO_POW_96, dec_denoms[1]],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{U256, Uint, uint};
+    use crate::error::PriceError;
+    use super::sqrt_price_x96_to_price;
+
+    type U1024 = Uint<1024, 16>;
+
+    const MIN_SQRT_RATIO: U256 = uint!(4295128739_U256);
+    const MAX_SQRT_RATIO: U256 = uint!(1461446703485210103287273052203988822378723970342_U256);
+    const DECIMAL_PAIRS: [(u64, u64); 4] = [(18, 18), (18, 6), (6, 18), (6, 6)];
+
+    fn pow10(exp: u64) -> U256 {
+        U256::from(10u64).pow(U256::from(exp))
+    }
+
+    /// The price as the exact fraction `precision_factor * num / den`.
+    fn exact_price(sqrt_price_x96: U256, inverse_it: bool, dec_denoms: [U256; 2]) -> (U1024, U1024) {
+        let sqrt_price = U1024::from(sqrt_price_x96);
+        let token0_in_token1 = (
+            sqrt_price * sqrt_price * U1024::from(dec_denoms[0]),
+            (U1024::from(1u8) << 192) * U1024::from(dec_denoms[1]),
+        );
+        if inverse_it {
+            (token0_in_token1.1, token0_in_token1.0)
+        } else {
+            token0_in_token1
+        }
+    }
+
+    /// Checks the price is the floor of the exact fraction, or `Overflow`
+    /// exactly when that floor doesn't fit in 256 bits.
+    fn assert_exact(sqrt_price_x96: U256, inverse_it: bool, dec_denoms: [U256; 2], precision_factor: U256) {
+        let (num, den) = exact_price(sqrt_price_x96, inverse_it, dec_denoms);
+        let expected = U1024::from(precision_factor) * num / den;
+        match sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, precision_factor) {
+            Ok(price) => assert_eq!(U1024::from(price), expected, "sqrtPriceX96 {sqrt_price_x96}, inverse_it {inverse_it}"),
+            Err(PriceError::Overflow) => assert!(
+                expected > U1024::from(U256::MAX),
+                "unexpected overflow at sqrtPriceX96 {sqrt_price_x96}, inverse_it {inverse_it}",
+            ),
+            Err(error) => panic!("{error:?} at sqrtPriceX96 {sqrt_price_x96}, inverse_it {inverse_it}"),
+        }
+    }
+
+    #[test]
+    fn exact_at_tick_bounds() {
+        for sqrt_price_x96 in [MIN_SQRT_RATIO, MAX_SQRT_RATIO] {
+            for (decimals0, decimals1) in DECIMAL_PAIRS {
+                for inverse_it in [false, true] {
+                    for precision in [0, 15, 18] {
+                        assert_exact(sqrt_price_x96, inverse_it, [pow10(decimals0), pow10(decimals1)], pow10(precision));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exact_at_random_prices() {
+        // xorshift64, seeded for reproducible points
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..1000 {
+            let random = U256::from_limbs([next(), next(), next(), next()]);
+            let sqrt_price_x96 = MIN_SQRT_RATIO + random % (MAX_SQRT_RATIO - MIN_SQRT_RATIO);
+            for (decimals0, decimals1) in DECIMAL_PAIRS {
+                for inverse_it in [false, true] {
+                    assert_exact(sqrt_price_x96, inverse_it, [pow10(decimals0), pow10(decimals1)], pow10(15));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_to_one_at_two_pow_96() {
+        let sqrt_price_x96 = U256::from(1u8) << 96;
+        for inverse_it in [false, true] {
+            assert_eq!(sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, [pow10(18); 2], pow10(15)), Ok(pow10(15)));
+        }
+        // equal raw amounts of an 18 and a 6 decimals token differ 1e12 in value
+        assert_eq!(sqrt_price_x96_to_price(sqrt_price_x96, false, [pow10(18), pow10(6)], pow10(15)), Ok(pow10(27)));
+        assert_eq!(sqrt_price_x96_to_price(sqrt_price_x96, true, [pow10(18), pow10(6)], pow10(15)), Ok(pow10(3)));
+    }
+
+    /// The largest `precision_factor` whose price fits in 256 bits prices
+    /// exactly, one more overflows.
+    #[test]
+    fn precision_overflow_boundary() {
+        let extreme_cases = [
+            (MAX_SQRT_RATIO, false, [pow10(18), pow10(6)]),
+            (MIN_SQRT_RATIO, true, [pow10(6), pow10(18)]),
+        ];
+        for (sqrt_price_x96, inverse_it, dec_denoms) in extreme_cases {
+            let (num, den) = exact_price(sqrt_price_x96, inverse_it, dec_denoms);
+            let max_precision_factor = U256::from(((U1024::from(1u8) << 256) * den - U1024::from(1u8)) / num);
+
+            assert_exact(sqrt_price_x96, inverse_it, dec_denoms, max_precision_factor);
+            assert!(sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, max_precision_factor).is_ok());
+            assert_eq!(
+                sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, max_precision_factor + U256::from(1u8)),
+                Err(PriceError::Overflow),
+            );
+        }
+    }
+}
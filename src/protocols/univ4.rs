@@ -0,0 +1,141 @@
+use alloy::primitives::{Address, B256, U256, keccak256, aliases::{U24, I24}};
+use alloy::sol_types::SolValue;
+use alloy::sol;
+use eyre::{Result, eyre};
+use crate::error::PriceError;
+use super::common::{self, DeploymentProbe, Protocol, StateRead, ViewCaller};
+use super::univ3::{self, U160_MASK};
+
+
+const UNIV4_POOLS_SLOT: B256 = B256::with_last_byte(6);
+
+sol! {
+    struct PoolKey {
+        address currency0;
+        address currency1;
+        uint24 fee;
+        int24 tickSpacing;
+        address hooks;
+    }
+}
+
+/// Pool living inside the V4 singleton PoolManager, identified either by its
+/// full PoolKey or directly by its PoolId.
+///
+/// A PoolId is a hash of the key, so when it is given directly `currency0`
+/// and `currency1` can't be checked against it and are trusted as configured.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct UniV4 {
+    pool_manager: Address,
+    currency0: Address,
+    currency1: Address,
+    #[serde(flatten)]
+    pool: PoolIdentifier,
+    #[serde(skip)]
+    resolved_pool_id: Option<B256>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PoolIdentifier {
+    Id {
+        pool_id: B256,
+    },
+    Key {
+        fee: u32,
+        tick_spacing: i32,
+        #[serde(default)]
+        hooks: Address,
+    },
+}
+
+impl UniV4 {
+
+    fn pool_id(&self) -> B256 {
+        self.resolved_pool_id.expect("UniV4 pool id not prepared")
+    }
+
+    fn slot0_slot(&self) -> B256 {
+        // `Pool.State` starts with `Slot0`, so the mapping entry itself holds it
+        common::mapping_slot(self.pool_id(), UNIV4_POOLS_SLOT)
+    }
+
+    /// PoolId of the configured pool, checking the key the way the
+    /// PoolManager does on initialization.
+    fn compute_pool_id(&self) -> Result<B256> {
+        // also for a given pool id, as swapped currencies would invert every price
+        if self.currency0 >= self.currency1 {
+            return Err(eyre!("UniV4 currency0 {} must sort before currency1 {}", self.currency0, self.currency1));
+        }
+        let (fee, tick_spacing, hooks) = match &self.pool {
+            PoolIdentifier::Id { pool_id } => return Ok(*pool_id),
+            PoolIdentifier::Key { fee, tick_spacing, hooks } => (*fee, *tick_spacing, *hooks),
+        };
+        if !(1..=i32::from(i16::MAX)).contains(&tick_spacing) {
+            return Err(eyre!("UniV4 tick_spacing {tick_spacing} must be within 1..=32767"));
+        }
+        let key = PoolKey {
+            currency0: self.currency0,
+            currency1: self.currency1,
+            fee: U24::try_from(fee).map_err(|_| eyre!("UniV4 fee {fee} is out of uint24 range"))?,
+            tickSpacing: I24::try_from(tick_spacing)?,
+            hooks,
+        };
+        Ok(keccak256(key.abi_encode()))
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Protocol for UniV4 {
+    fn name(&self) -> String {
+        format!("UniV4: {}", self.pool_id())
+    }
+
+    fn pool_address(&self) -> Address {
+        self.pool_manager
+    }
+
+    async fn prepare(&mut self, _caller: &dyn ViewCaller) -> Result<()> {
+        self.resolved_pool_id = Some(self.compute_pool_id()?);
+        Ok(())
+    }
+
+    fn state_reads(&self) -> Vec<StateRead> {
+        vec![StateRead::storage(self.pool_manager, self.slot0_slot())]
+    }
+
+    /// The pool exists from its initialization, which sets `Slot0`.
+    fn deployment_probes(&self) -> Vec<DeploymentProbe> {
+        vec![DeploymentProbe::Storage { target: self.pool_manager, slot: self.slot0_slot() }]
+    }
+
+    fn retrieve_price_from_storage(
+        &self,
+        storage: &[U256],
+        inverse_it: bool,
+        dec_denoms: [U256; 2],
+        precision_factor: U256,
+    ) -> Result<U256, PriceError> {
+        let sqrt_price_x96 = storage[0] & U160_MASK;
+        univ3::sqrt_price_x96_to_price(sqrt_price_x96, inverse_it, dec_denoms, precision_factor)
+    }
+
+    async fn fetch_tokens(
+        &self,
+        _caller: &dyn ViewCaller,
+    ) -> Result<[Address; 2]> {
+        Ok([self.currency0, self.currency1])
+    }
+
+    /// The PoolManager has no per-pool getters to compare with, so only
+    /// checks that `Slot0` is set: an empty one means the pool id or key
+    /// doesn't name an initialized pool.
+    async fn verify_layout(&self, caller: &dyn ViewCaller) -> Result<()> {
+        let slot0 = caller.storage(self.pool_manager, self.slot0_slot()).await?;
+        if slot0.is_zero() {
+            return Err(eyre!("{} is not initialized in PoolManager {}", self.name(), self.pool_manager));
+        }
+        Ok(())
+    }
+}
@@ -1,40 +1,203 @@
 use std::path::Path;
+use std::ops::Range;
 use std::sync::Arc;
 use eyre::Result;
 
-use reth_ethereum::node::{api::NodeTypesWithDBAdapter, EthereumNode};
-use reth_ethereum::chainspec::ChainSpecBuilder;
+use alloy::consensus::{BlockHeader, Header};
+use alloy::primitives::{Address, B256, Bytes};
+use alloy::genesis::Genesis;
+use reth_ethereum::node::{api::{NodePrimitives, NodeTypes, NodeTypesWithDBAdapter}, EthereumNode};
+use reth_ethereum::chainspec::{ChainSpec, EthChainSpec, MAINNET, SEPOLIA, HOLESKY};
+use reth_ethereum::evm::EthEvmConfig;
+use reth_ethereum::evm::primitives::{ConfigureEvm, ConfigureEvmEnv, Evm};
+use reth_ethereum::evm::revm::database::StateProviderDatabase;
 use reth_ethereum::provider::{
     db::{mdbx::DatabaseArguments, open_db_read_only, ClientVersion, DatabaseEnv},
-    providers::StaticFileProvider,
+    db::{tables, cursor::DbCursorRO, transaction::DbTx, models::storage_sharded_key::StorageShardedKey},
+    providers::{ProviderNodeTypes, StaticFileProvider},
+    BlockHashReader,
+    BlockNumReader,
+    DBProvider,
     HeaderProvider,
     ProviderFactory,
 };
+use reth_op::chainspec::{OpChainSpec, BASE_MAINNET, BASE_SEPOLIA, OP_MAINNET, OP_SEPOLIA};
+use reth_op::evm::OpEvmConfig;
+use reth_op::node::OpNode;
 
-pub type LocalProviderFactory = ProviderFactory<NodeTypesWithDBAdapter<EthereumNode, Arc<DatabaseEnv>>>;
+pub type LocalProviderFactory<N = EthereumNode> = ProviderFactory<NodeTypesWithDBAdapter<N, Arc<DatabaseEnv>>>;
 
-pub fn build_provider_factory(db_path: &Path) -> Result<LocalProviderFactory> {
+/// Built-in chainspec for `chain_id`, or the one described by a genesis JSON file.
+pub fn chain_spec(chain_id: u64, genesis_path: Option<&Path>) -> Result<Arc<ChainSpec>> {
+    if let Some(genesis_path) = genesis_path {
+        return spec_from_genesis_file(chain_id, genesis_path);
+    }
+    match chain_id {
+        1 => Ok(MAINNET.clone()),
+        11155111 => Ok(SEPOLIA.clone()),
+        17000 => Ok(HOLESKY.clone()),
+        _ => Err(eyre::eyre!("No built-in chainspec for chain {}, set `genesis_path` in its config", chain_id)),
+    }
+}
+
+/// OP-stack counterpart of [`chain_spec`], for op-reth databases.
+pub fn op_chain_spec(chain_id: u64, genesis_path: Option<&Path>) -> Result<Arc<OpChainSpec>> {
+    if let Some(genesis_path) = genesis_path {
+        return spec_from_genesis_file(chain_id, genesis_path);
+    }
+    match chain_id {
+        10 => Ok(OP_MAINNET.clone()),
+        8453 => Ok(BASE_MAINNET.clone()),
+        11155420 => Ok(OP_SEPOLIA.clone()),
+        84532 => Ok(BASE_SEPOLIA.clone()),
+        _ => Err(eyre::eyre!("No built-in OP chainspec for chain {}, set `genesis_path` in its config", chain_id)),
+    }
+}
+
+fn spec_from_genesis_file<S>(chain_id: u64, genesis_path: &Path) -> Result<Arc<S>>
+where
+    S: EthChainSpec + From<Genesis>,
+{
+    let genesis_str = std::fs::read_to_string(genesis_path)
+        .map_err(|e| eyre::eyre!("Failed to read genesis file {:?}: {}", genesis_path, e))?;
+    let genesis: Genesis = serde_json::from_str(&genesis_str)
+        .map_err(|e| eyre::eyre!("Failed to parse genesis file {:?}: {}", genesis_path, e))?;
+    let spec = S::from(genesis);
+    if spec.chain().id() != chain_id {
+        return Err(eyre::eyre!(
+            "Genesis file {:?} is for chain {}, expected {}",
+            genesis_path,
+            spec.chain().id(),
+            chain_id,
+        ));
+    }
+    Ok(Arc::new(spec))
+}
+
+/// Opens the reth datadir at `db_path` read-only, for any node type
+/// (`EthereumNode` for reth, `OpNode` for op-reth).
+pub fn build_provider_factory<N>(db_path: &Path, spec: Arc<N::ChainSpec>) -> Result<LocalProviderFactory<N>>
+where
+    N: NodeTypes<ChainSpec: EthChainSpec>,
+    NodeTypesWithDBAdapter<N, Arc<DatabaseEnv>>: ProviderNodeTypes,
+{
     let db_path = Path::new(&db_path);
     let db = Arc::new(open_db_read_only(
         db_path.join("db").as_path(),
         DatabaseArguments::new(ClientVersion::default()),
     )?);
-    let spec = Arc::new(ChainSpecBuilder::mainnet().build());
-    let factory = ProviderFactory::<NodeTypesWithDBAdapter<EthereumNode, Arc<DatabaseEnv>>>::new(
+    let genesis_hash = spec.genesis_hash();
+    let chain_id = spec.chain().id();
+    let factory = LocalProviderFactory::<N>::new(
         db.clone(),
-        spec.clone(),
+        spec,
         StaticFileProvider::read_only(db_path.join("static_files"), true)?,
     );
+
+    let db_genesis_hash = factory
+        .block_hash(0)?
+        .ok_or_else(|| eyre::eyre!("Genesis block not found in {:?}", db_path))?;
+    if db_genesis_hash != genesis_hash {
+        return Err(eyre::eyre!(
+            "Genesis hash {} of {:?} does not match chainspec genesis {} (chain {})",
+            db_genesis_hash,
+            db_path,
+            genesis_hash,
+            chain_id,
+        ));
+    }
     Ok(factory)
 }
 
-pub fn block_num_to_timestamp(
-    provider: &LocalProviderFactory,
+/// Timestamp of `block_num`, `None` if its header is not in the database.
+pub fn block_num_to_timestamp<N: ProviderNodeTypes>(
+    provider: &ProviderFactory<N>,
+    block_num: u64,
+) -> Result<Option<u64>> {
+    Ok(provider.header_by_number(block_num)?.map(|h| h.timestamp()))
+}
+
+pub fn latest_block_number<N: ProviderNodeTypes>(provider: &ProviderFactory<N>) -> Result<u64> {
+    Ok(provider.best_block_number()?)
+}
+
+/// Node types [`call_at_block`] can execute calls for, with the EVM
+/// configured from their chainspec so that `CHAINID` and fork rules match
+/// the chain.
+pub trait CallEvmConfig: NodeTypes {
+    type EvmConfig: ConfigureEvm<Header = Header> + Send + Sync + 'static;
+
+    fn evm_config(chain_spec: Arc<Self::ChainSpec>) -> Self::EvmConfig;
+}
+
+impl CallEvmConfig for EthereumNode {
+    type EvmConfig = EthEvmConfig;
+
+    fn evm_config(chain_spec: Arc<ChainSpec>) -> EthEvmConfig {
+        EthEvmConfig::new(chain_spec)
+    }
+}
+
+impl CallEvmConfig for OpNode {
+    type EvmConfig = OpEvmConfig;
+
+    fn evm_config(chain_spec: Arc<OpChainSpec>) -> OpEvmConfig {
+        OpEvmConfig::optimism(chain_spec)
+    }
+}
+
+/// Executes a read-only call against the state at the end of `block_num`
+/// with a local EVM, so no RPC is needed for view functions.
+///
+/// The outer error is a database or EVM failure, the inner one describes a
+/// call that reverted, halted or returned nothing.
+pub fn call_at_block<N, E>(
+    provider: &ProviderFactory<N>,
+    evm_config: &E,
     block_num: u64,
-) -> Result<u64> {
-    provider
+    to: Address,
+    input: Bytes,
+) -> Result<Result<Bytes, String>>
+where
+    N: ProviderNodeTypes<Primitives: NodePrimitives<BlockHeader = Header>>,
+    E: ConfigureEvm<Header = Header>,
+{
+    let header = provider
         .header_by_number(block_num)?
-        .map(|h| h.timestamp)
-        .ok_or_else(|| eyre::eyre!("Header not found for block number {}", block_num))
+        .ok_or_else(|| eyre::eyre!("Header not found for block number {}", block_num))?;
+    let state = provider.history_by_block_number(block_num)?;
+
+    let evm_env = evm_config.evm_env(&header);
+    let mut evm = evm_config.evm_with_env(StateProviderDatabase::new(state), evm_env);
+    let result = evm.transact_system_call(Address::ZERO, to, input)?.result;
+    if !result.is_success() {
+        return Ok(Err(format!("{result:?}")));
+    }
+    Ok(result.into_output().ok_or_else(|| "no output".to_string()))
 }
 
+/// Blocks within `block_range` in which the storage slot was modified,
+/// taken from the `StoragesHistory` index instead of reading every block.
+pub fn storage_change_blocks<N: ProviderNodeTypes>(
+    provider: &ProviderFactory<N>,
+    address: Address,
+    slot: B256,
+    block_range: Range<u64>,
+) -> Result<Vec<u64>> {
+    let provider = provider.provider()?;
+    let mut cursor = provider.tx_ref().cursor_read::<tables::StoragesHistory>()?;
+    let start_key = StorageShardedKey::new(address, slot, block_range.start);
+
+    let mut blocks = Vec::new();
+    for entry in cursor.walk(Some(start_key))? {
+        let (key, shard) = entry?;
+        if key.address != address || key.sharded_key.key != slot {
+            break;
+        }
+        blocks.extend(shard.iter().filter(|block| block_range.contains(block)));
+        if key.sharded_key.highest_block_number >= block_range.end {
+            break;
+        }
+    }
+    Ok(blocks)
+}
@@ -5,11 +5,22 @@ use parquet::arrow::arrow_writer::ArrowWriter;
 use parquet::file::properties::WriterProperties;
 use arrow::datatypes::FieldRef;
 use serde_arrow::schema::{SchemaLike, TracingOptions};
-use crate::{PriceFetcherResult, PricesMetadata};
+use crate::{Candle, PriceFetcherResult, PricesMetadata};
 
 
 pub fn write_prices_to_parquet(records: &Vec<PriceFetcherResult>, out_path: &Path) -> Result<()> {
-    let fields = Vec::<FieldRef>::from_samples(records, TracingOptions::default())?;
+    write_records_to_parquet(records, out_path)
+}
+
+pub fn write_candles_to_parquet(records: &[Candle], out_path: &Path) -> Result<()> {
+    write_records_to_parquet(records, out_path)
+}
+
+fn write_records_to_parquet<T: serde::Serialize>(records: &[T], out_path: &Path) -> Result<()> {
+    let tracing_options = TracingOptions::default()
+        .allow_null_fields(true)
+        .enums_without_data_as_strings(true);
+    let fields = Vec::<FieldRef>::from_samples(records, tracing_options)?;
     let batch = serde_arrow::to_record_batch(&fields, &records)?;
 
     let file = File::create(out_path)?;
@@ -0,0 +1,70 @@
+//! Runs the RPC backend against a local anvil fork of mainnet.
+//!
+//! Needs `anvil` on the PATH and an archive endpoint to fork from:
+//! `ETH_RPC_URL=... cargo test --test rpc_backend -- --ignored`
+
+use std::ops::Range;
+
+use alloy::node_bindings::Anvil;
+use url::Url;
+
+use pool_price_fetcher::{BackendType, ChainConfig, PriceFetcherBuilder, PriceFetcherResult, PriceStatus};
+
+
+const FORK_BLOCK: u64 = 20_000_000;
+
+const SOURCES: &str = r#"
+chain_id = 1
+default_start_block = 0
+default_end_block = 0
+
+[[price_sources]]
+name = "usdc_eth_univ3"
+inverse_it = true
+protocol = { type = "univ3", pool = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640" }
+
+[[price_sources]]
+name = "usdc_eth_univ2"
+inverse_it = true
+protocol = { type = "univ2", pool = "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc" }
+
+[[price_sources]]
+name = "eth_usd_chainlink"
+inverse_it = false
+protocol = { type = "chainlink", proxy = "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419", base_token = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", quote_token = "0x0000000000000000000000000000000000000348" }
+"#;
+
+async fn fetch(rpc_url: Url, batch_size: usize, blocks: Range<u64>) -> Vec<PriceFetcherResult> {
+    let chain_config: ChainConfig = toml::from_str(SOURCES).unwrap();
+    let fetcher = PriceFetcherBuilder::new()
+        .precision(15)
+        .chain_id(chain_config.chain_id)
+        .backend(BackendType::Rpc)
+        .rpc_url(rpc_url)
+        .rpc_batch_size(batch_size)
+        .price_sources(chain_config.price_sources)
+        .build()
+        .await
+        .unwrap();
+    tokio::task::spawn_blocking(move || fetcher.fetch_prices(blocks)).await.unwrap().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs anvil and an archive node in ETH_RPC_URL"]
+async fn batched_reads_match_per_block_reads() {
+    let fork_url = std::env::var("ETH_RPC_URL").expect("ETH_RPC_URL is not set");
+    let anvil = Anvil::new().fork(fork_url).fork_block_number(FORK_BLOCK).spawn();
+    let blocks = FORK_BLOCK - 20..FORK_BLOCK + 1;
+
+    // a batch per block's reads versus batches spanning several blocks
+    let unbatched = fetch(anvil.endpoint_url(), 1, blocks.clone()).await;
+    let batched = fetch(anvil.endpoint_url(), 7, blocks.clone()).await;
+
+    assert_eq!(batched.len(), 3 * blocks.len());
+    assert!(batched.iter().all(|row| row.status == PriceStatus::Ok && row.block_timestamp.is_some()));
+    let key = |row: &PriceFetcherResult| (row.block_num, row.source.clone(), row.block_timestamp, row.price);
+    assert_eq!(
+        batched.iter().map(key).collect::<Vec<_>>(),
+        unbatched.iter().map(key).collect::<Vec<_>>(),
+    );
+}